@@ -0,0 +1,95 @@
+/*
+Copyright (C) 2020 Dorian Rudolph
+
+sirula is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+sirula is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with sirula.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+// Times typing a query into a list of synthetic entries, once matching every entry for every
+// keystroke and once only matching the entries that matched the previous keystroke.
+// Run with `cargo bench`.
+
+#[allow(dead_code)]
+#[path = "../src/matcher.rs"]
+mod matcher;
+
+use matcher::{MatchMode, Matcher};
+use std::time::{Duration, Instant};
+
+const ENTRIES: usize = 10_000;
+const QUERIES: [&str; 3] = ["fire", "text edit", "zurich"];
+const WORDS: [&str; 24] = [
+    "Fire", "fox", "Text", "Editor", "Libre", "Office", "Writer", "Calc", "Terminal", "Sound",
+    "Settings", "Image", "Viewer", "Music", "Player", "Video", "Mail", "Client", "Browser",
+    "Files", "Manager", "Zürich", "Café", "Mönch",
+];
+
+// deterministic pseudo random names of two to four words
+fn entries() -> Vec<String> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as usize
+    };
+    (0..ENTRIES)
+        .map(|_| {
+            let words = 2 + next() % 3;
+            (0..words).map(|_| WORDS[next() % WORDS.len()]).collect::<Vec<_>>().join(" ")
+        })
+        .collect()
+}
+
+// time for typing the query char by char, returns the time and the number of final matches
+fn type_query(matcher: &Matcher, entries: &[String], query: &str, narrow: bool) -> (Duration, usize) {
+    let start = Instant::now();
+    let mut matches: Vec<&String> = entries.iter().collect();
+    for (i, _) in query.char_indices().skip(1).chain([(query.len(), ' ')]) {
+        let candidates: Vec<&String> = if narrow { matches } else { entries.iter().collect() };
+        matches = candidates
+            .into_iter()
+            .filter(|e| matcher.fuzzy_match(e, &query[..i]).is_some())
+            .collect();
+    }
+    (start.elapsed(), matches.len())
+}
+
+fn main() {
+    let entries = entries();
+    println!("{} entries", entries.len());
+    let mut mode = MatchMode::Fuzzy;
+    loop {
+        let matcher = Matcher::new(mode);
+        for query in QUERIES {
+            let (full, full_matches) = type_query(&matcher, &entries, query, false);
+            let (incremental, matches) = type_query(&matcher, &entries, query, true);
+            // regex patterns do not only get narrower when extended
+            if mode != MatchMode::Regex {
+                assert_eq!(full_matches, matches);
+            }
+            println!(
+                "{:<14} {:<13} {:>6} matches  full {:>9.2?}  incremental {:>9.2?}",
+                mode.label(),
+                format!("\"{}\"", query),
+                full_matches,
+                full,
+                incremental
+            );
+        }
+        mode = mode.next();
+        if mode == MatchMode::Fuzzy {
+            break;
+        }
+    }
+}
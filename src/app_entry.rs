@@ -18,67 +18,276 @@ along with sirula.  If not, see <https://www.gnu.org/licenses/>.
 */
 
 use crate::locale::string_collate;
-use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
-use gio::AppInfo;
+use gio::{AppInfo, DesktopAppInfo, FileIcon, Icon};
 use glib::shell_unquote;
-use gtk::{
-    builders::{BoxBuilder, ImageBuilder, LabelBuilder},
-    prelude::*,
-    IconLookupFlags, IconTheme, Label, ListBoxRow, Orientation,
-};
-use pango::{AttrList, Attribute, EllipsizeMode};
+use gtk::{prelude::*, IconLookupFlags, IconTheme};
+use pango::{AttrList, Attribute};
+use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::collections::HashMap;
-
-use super::{consts::*, Config, Field, HistoryData};
+use std::time::Instant;
+
+use super::{
+    consts::*,
+    entry_cache::{cache_key, modified, read_entry_cache, write_entry_cache, CachedEntry},
+    util::app_command,
+    history::{now, QueryData},
+    matcher::{typo_match, Matcher},
+    Config, CustomEntry, Field, History, HistoryData, Ranking,
+};
 use regex::RegexSet;
 
-#[derive(Eq)]
+// App of an entry. Entries from the cache only know the desktop file id of their app, its
+// desktop file is loaded when it is launched.
+pub struct AppHandle {
+    pub id: String,
+    // modification time of the desktop file when the entry was created
+    modified: Option<u64>,
+    info: RefCell<Option<AppInfo>>,
+}
+
+impl AppHandle {
+    fn new(id: &str, info: AppInfo) -> AppHandle {
+        AppHandle {
+            id: id.to_string(),
+            modified: desktop_file_modified(&info),
+            info: RefCell::new(Some(info)),
+        }
+    }
+
+    fn cached(id: String, modified: Option<u64>) -> AppHandle {
+        AppHandle {
+            id,
+            modified,
+            info: RefCell::new(None),
+        }
+    }
+
+    pub fn info(&self) -> Option<AppInfo> {
+        let mut info = self.info.borrow_mut();
+        if info.is_none() {
+            *info = DesktopAppInfo::new(&self.id).map(|d| d.upcast());
+            if info.is_none() {
+                eprintln!("Cannot find desktop file {}", self.id);
+            }
+        }
+        info.clone()
+    }
+}
+
+impl PartialEq for AppHandle {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for AppHandle {}
+
+#[derive(PartialEq, Eq)]
+pub enum EntryKind {
+    App(AppHandle),
+    // line read from stdin in dmenu mode, together with its position in the input, the bytes
+    // are kept as they were read since they need not be UTF-8
+    Dmenu(usize, Vec<u8>),
+    // desktop action (e.g., "new-window") of an app
+    Action(AppHandle, String),
+    // [[entry]] from the config
+    Custom(CustomEntry),
+}
+
+impl EntryKind {
+    // key under which usage of the entry is recorded in the history
+    pub fn history_id(&self) -> Option<String> {
+        match self {
+            EntryKind::App(app) => Some(app.id.clone()),
+            EntryKind::Action(app, action) => Some(format!("{}:{}", app.id, action)),
+            EntryKind::Custom(custom) => Some(format!("custom:{}", custom.name)),
+            EntryKind::Dmenu(..) => None,
+        }
+    }
+}
+
 pub struct AppEntry {
     pub display_string: String,
-    pub search_string: String,
+    // hidden fields that are searched in addition to display_string, with their weights
+    pub search_fields: Vec<(String, f64)>,
     pub extra_range: Option<(u32, u32)>,
-    pub info: AppInfo,
-    pub label: Label,
+    pub kind: EntryKind,
+    pub icon: Option<Icon>,
+    // score of the match alone, combined with the history into score
+    pub match_score: i64,
     pub score: i64,
+    // history for sorting, only the parts that are enabled for ranking are set
     pub history: HistoryData,
+    // launches for the history boost, weighted by their age with ranking = "frecency"
+    launches: f64,
+    // how often the entry was launched for the current query
+    pub selections: u32,
+    pub pinned: bool,
+    // pinned entries come first while the query is empty
+    empty_query: bool,
+    // matched only with typos, ranked below all other matches
+    pub typo: bool,
+    // char indices of display_string that matched the query
+    highlights: Vec<usize>,
 }
 
 impl AppEntry {
-    pub fn update_match(&mut self, pattern: &str, matcher: &SkimMatcherV2, config: &Config) {
-        self.set_markup(config);
+    fn new(
+        display_string: String,
+        extra_range: Option<(u32, u32)>,
+        search_fields: Vec<(String, f64)>,
+        kind: EntryKind,
+        icon: Option<Icon>,
+    ) -> AppEntry {
+        AppEntry {
+            display_string,
+            search_fields,
+            extra_range,
+            kind,
+            icon,
+            match_score: 100,
+            score: 100,
+            history: HistoryData::default(),
+            launches: 0.0,
+            selections: 0,
+            pinned: false,
+            empty_query: true,
+            typo: false,
+            highlights: Vec::new(),
+        }
+    }
 
-        let attr_list = self.label.attributes().unwrap_or_default();
-        self.score = if pattern.is_empty() {
-            self.label.set_attributes(None);
+    // take the launches and the pinned state of the entry from the history
+    fn with_history(mut self, history: &History, config: &Config) -> AppEntry {
+        if let Some(id) = self.kind.history_id() {
+            self.set_history(history, &id, config);
+            self.set_pinned(history.pinned.contains(&id), config);
+        }
+        self
+    }
+
+    // take the launches of the entry with the given history id from the history
+    pub fn set_history(&mut self, history: &History, id: &str, config: &Config) {
+        self.history = entry_history(history, id, config);
+        let data = history.apps.get(id).copied().unwrap_or_default();
+        self.launches = if config.ranking == Ranking::Frecency {
+            data.frecency_at(now(), config.frecency_half_life)
+        } else {
+            data.usage_count as f64
+        };
+        self.update_score(config);
+    }
+
+    pub fn update_match(
+        &mut self,
+        pattern: &str,
+        matcher: &Matcher,
+        config: &Config,
+        selections: Option<&HashMap<String, QueryData>>,
+    ) {
+        self.typo = false;
+        self.empty_query = pattern.is_empty();
+        self.selections = selections
+            .zip(self.kind.history_id())
+            .and_then(|(s, id)| s.get(&id))
+            .map_or(0, |q| q.count);
+
+        self.highlights.clear();
+        self.match_score = if pattern.is_empty() {
             100
-        } else if let Some((score, indices)) = matcher.fuzzy_indices(&self.search_string, pattern) {
-            let mut chars = vec![];
-            for cur in self.search_string.as_str().char_indices() {
-                chars.push(cur);
+        } else {
+            // only the displayed string is highlighted
+            let mut display_match = matcher.fuzzy_indices(&self.display_string, pattern);
+
+            // prefer acronyms of the name (e.g., "vsc" for "Visual Studio Code") over incidental matches
+            if let Some(initials) = matcher.match_initials(self.name(), pattern) {
+                let score = display_match.map_or(0, |(score, _)| score.max(0));
+                display_match = Some((score + config.acronym_boost, initials));
             }
-            chars.push((self.search_string.len(), ' '));
-
-            for i in indices {
-                if i < self.display_string.len() {
-                    let i = i as usize;
-                    add_attrs(
-                        &attr_list,
-                        &config.markup_highlight,
-                        chars[i].0 as u32,
-                        chars[i + 1].0 as u32,
-                    );
-                }
+
+            // the best weighted match of all fields, fields with a weight of 0 do not match, and
+            // a positive match is at least 1 so that rounding does not hide the entry
+            let score = self
+                .search_fields
+                .iter()
+                .filter_map(|(field, weight)| {
+                    let score = matcher.fuzzy_match(field, pattern)?;
+                    Some(score as f64 * weight).filter(|_| score > 0)
+                })
+                .chain(display_match.as_ref().map(|(score, _)| *score as f64))
+                .filter(|&score| score > 0.0)
+                .reduce(f64::max)
+                .map_or(0, |score| (score.round() as i64).max(1));
+            if let Some((_, indices)) = display_match {
+                self.highlights = indices;
             }
             score
-        } else {
+        };
+        self.update_score(config);
+    }
+
+    // fallback for when no entry matches, matches the words of the name with a few typos
+    pub fn update_typo_match(&mut self, pattern: &str, config: &Config) {
+        let typo_match = typo_match(self.name(), pattern, config.max_typos);
+        self.typo = typo_match.is_some();
+        (self.match_score, self.highlights) = typo_match.unwrap_or_default();
+        self.update_score(config);
+    }
+
+    // the displayed string without the extra text
+    fn name(&self) -> &str {
+        let end = self
+            .extra_range
+            .map_or(self.display_string.len(), |(lo, _)| (lo as usize).saturating_sub(1));
+        &self.display_string[..end]
+    }
+
+    // combine the match score with the history
+    pub fn update_score(&mut self, config: &Config) {
+        let (boost, query_boost, pinned_boost) = self.boosts(config);
+        self.score = if self.match_score == 0 {
             0
+        } else {
+            self.match_score + boost + query_boost + pinned_boost
         };
+    }
+
+    // history, query and pinned boost
+    fn boosts(&self, config: &Config) -> (i64, i64, i64) {
+        let query_boost = (config.query_weight * (self.selections as f64).ln_1p()).round() as i64;
+        let pinned_boost = if self.pinned { config.pinned_boost } else { 0 };
+        (self.history_boost(config), query_boost, pinned_boost)
+    }
 
-        self.label.set_attributes(Some(&attr_list));
+    // how the score came about, shown as tooltip if debug_scores is set
+    pub fn tooltip(&self, config: &Config) -> Option<String> {
+        if !config.debug_scores {
+            return None;
+        }
+        let (boost, query_boost, pinned_boost) = self.boosts(config);
+        Some(format!(
+            "score {} = match {} + history {} + query {} + pinned {}",
+            self.score, self.match_score, boost, query_boost, pinned_boost
+        ))
+    }
+
+    // bonus for often used entries, grows logarithmically with the number of launches
+    fn history_boost(&self, config: &Config) -> i64 {
+        (config.history_weight * self.launches.ln_1p()).round() as i64
+    }
+
+    pub fn set_pinned(&mut self, pinned: bool, config: &Config) {
+        self.pinned = pinned;
+        self.update_score(config);
+    }
+
+    fn pinned_first(&self) -> bool {
+        self.pinned && self.empty_query
     }
 
     pub fn hide(&mut self) {
+        self.match_score = 0;
         self.score = 0;
     }
 
@@ -86,7 +295,8 @@ impl AppEntry {
         0 == self.score
     }
 
-    fn set_markup(&self, config: &Config) {
+    // attributes of the label, built only when the entry is shown
+    pub fn markup(&self, config: &Config) -> AttrList {
         let attr_list = AttrList::new();
 
         add_attrs(
@@ -98,22 +308,53 @@ impl AppEntry {
         if let Some((lo, hi)) = self.extra_range {
             add_attrs(&attr_list, &config.markup_extra, lo, hi);
         }
-        self.label.set_attributes(Some(&attr_list));
+
+        // highlight the matched chars
+        let mut chars = vec![];
+        for cur in self.display_string.as_str().char_indices() {
+            chars.push(cur);
+        }
+        chars.push((self.display_string.len(), ' '));
+        for &i in &self.highlights {
+            add_attrs(
+                &attr_list,
+                &config.markup_highlight,
+                chars[i].0 as u32,
+                chars[i + 1].0 as u32,
+            );
+        }
+        attr_list
     }
 }
 
 impl PartialEq for AppEntry {
     fn eq(&self, other: &Self) -> bool {
-        self.score.eq(&other.score) && self.history.eq(&other.history)
+        self.pinned_first().eq(&other.pinned_first())
+            && self.typo.eq(&other.typo)
+            && self.score.eq(&other.score)
+            && self.history.eq(&other.history)
     }
 }
 
+impl Eq for AppEntry {}
+
 impl Ord for AppEntry {
     fn cmp(&self, other: &Self) -> Ordering {
-        match self.score.cmp(&other.score) {
+        let frecency = self
+            .history
+            .frecency
+            .partial_cmp(&other.history.frecency)
+            .unwrap_or(Ordering::Equal);
+        let pinned = self.pinned_first().cmp(&other.pinned_first());
+        let typo = other.typo.cmp(&self.typo);
+        match pinned.then(typo).then(self.score.cmp(&other.score)).then(frecency) {
             Ordering::Equal => match self.history.usage_count.cmp(&other.history.usage_count) {
                 Ordering::Equal => match self.history.last_used.cmp(&other.history.last_used) {
-                    Ordering::Equal => string_collate(&self.display_string, &other.display_string),
+                    Ordering::Equal => match (&self.kind, &other.kind) {
+                        // keep the input order in dmenu mode
+                        (EntryKind::Dmenu(a, _), EntryKind::Dmenu(b, _)) => a.cmp(b),
+                        _ => string_collate(&self.display_string, &other.display_string),
+                    },
                     ord => ord.reverse(),
                 },
                 ord => ord.reverse(),
@@ -145,8 +386,30 @@ fn get_app_field(app: &AppInfo, field: Field) -> Option<String> {
             .file_name()
             .and_then(|e| shell_unquote(e).ok())
             .map(|s| s.to_string_lossy().to_string()),
-        //TODO: clean up command line from % for all what is not done in launch_app() in src/util.rx
-        Field::Commandline => app.commandline().map(|s| s.to_string_lossy().to_string()),
+        Field::Commandline => app
+            .commandline()
+            .and_then(|s| app_command(app, &s.to_string_lossy()).ok())
+            .map(|command| command.join(" ")),
+        Field::Keywords => app
+            .downcast_ref::<DesktopAppInfo>()
+            .map(|d| d.keywords().iter().map(|k| k.as_str()).collect::<Vec<_>>().join(" "))
+            .filter(|k| !k.is_empty()),
+        Field::GenericName => app
+            .downcast_ref::<DesktopAppInfo>()
+            .and_then(|d| d.generic_name())
+            .map(Into::into),
+        Field::Categories => app
+            .downcast_ref::<DesktopAppInfo>()
+            .and_then(|d| d.categories())
+            .map(|c| c.split(';').filter(|c| !c.is_empty()).collect::<Vec<_>>().join(" ")),
+        Field::Name => app
+            .downcast_ref::<DesktopAppInfo>()
+            .and_then(|d| d.string("Name"))
+            .map(Into::into),
+        Field::DesktopFilePath => app
+            .downcast_ref::<DesktopAppInfo>()
+            .and_then(|d| d.filename())
+            .map(|f| f.to_string_lossy().to_string()),
     }
 }
 
@@ -159,22 +422,117 @@ fn add_attrs(list: &AttrList, attrs: &Vec<Attribute>, start: u32, end: u32) {
     }
 }
 
+// Load the entries of the apps, from the cache unless something changed since it was written,
+// and the custom entries. With timing, print how long loading the apps took.
 pub fn load_entries(
     config: &Config,
-    history: &HashMap<String, HistoryData>,
-) -> HashMap<ListBoxRow, AppEntry> {
-    let mut entries = HashMap::new();
+    history: &History,
+    rebuild_cache: bool,
+    timing: bool,
+) -> Vec<AppEntry> {
+    let start = Instant::now();
+    let cached = if rebuild_cache {
+        None
+    } else {
+        read_entry_cache(&cache_key())
+    };
+    let from_cache = cached.is_some();
+    let mut entries: Vec<AppEntry> = match cached {
+        Some(cached) => cached
+            .into_iter()
+            .map(|c| cached_entry(c, config, history))
+            .collect(),
+        None => {
+            let entries: Vec<AppEntry> = load_apps(config)
+                .into_iter()
+                .flat_map(|(id, app)| load_app_entries(&id, &app, config, history))
+                .collect();
+            save_entry_cache(&entries, config);
+            entries
+        }
+    };
+    if timing {
+        eprintln!(
+            "Loaded {} app entries from {} in {:.1?}",
+            entries.len(),
+            if from_cache { "the cache" } else { "desktop files" },
+            start.elapsed()
+        );
+    }
+
+    entries.extend(
+        config
+            .custom_entries
+            .iter()
+            .map(|custom| load_custom_entry(custom, config, history)),
+    );
+    entries
+}
+
+// write the app entries to the cache, together with the files of their icons
+fn save_entry_cache(entries: &[AppEntry], config: &Config) {
     let icon_theme = IconTheme::default().unwrap();
-    let apps = gio::AppInfo::all();
+    let cached = entries
+        .iter()
+        .filter_map(|e| {
+            let (app, action) = match &e.kind {
+                EntryKind::App(app) => (app, None),
+                EntryKind::Action(app, action) => (app, Some(action.clone())),
+                EntryKind::Custom(..) | EntryKind::Dmenu(..) => return None,
+            };
+            let icon = e
+                .icon
+                .as_ref()
+                .and_then(|icon| {
+                    icon_theme.lookup_by_gicon(icon, config.icon_size, IconLookupFlags::FORCE_SIZE)
+                })
+                .and_then(|info| info.filename())
+                .map(|file| file.to_string_lossy().to_string());
+            Some(CachedEntry {
+                id: app.id.clone(),
+                action,
+                modified: app.modified,
+                display_string: e.display_string.clone(),
+                extra_range: e.extra_range,
+                search_fields: e.search_fields.clone(),
+                icon,
+            })
+        })
+        .collect();
+    if let Err(err) = write_entry_cache(cache_key(), cached) {
+        eprintln!("Cannot write entry cache: {}", err);
+    }
+}
+
+fn cached_entry(cached: CachedEntry, config: &Config, history: &History) -> AppEntry {
+    let app = AppHandle::cached(cached.id, cached.modified);
+    let kind = match cached.action {
+        Some(action) => EntryKind::Action(app, action),
+        None => EntryKind::App(app),
+    };
+    let icon = cached
+        .icon
+        .map(|file| FileIcon::new(&gio::File::for_path(file)).upcast());
+    AppEntry::new(
+        cached.display_string,
+        cached.extra_range,
+        cached.search_fields,
+        kind,
+        icon,
+    )
+    .with_history(history, config)
+}
+
+// apps that should be listed, by id
+fn load_apps(config: &Config) -> HashMap<String, AppInfo> {
+    let mut apps = HashMap::new();
     let exclude = RegexSet::new(&config.exclude).expect("Invalid regex");
 
-    for app in apps {
+    for app in gio::AppInfo::all() {
         if !app.should_show() {
             continue;
         }
 
-        let name = app.display_name().to_string();
-
         let id = match app.id() {
             Some(id) => id.to_string(),
             _ => continue,
@@ -184,110 +542,216 @@ pub fn load_entries(
             continue;
         }
 
-        let (display_string, extra_range) = if let Some(name) =
-            get_app_field(&app, Field::Id).and_then(|id| config.name_overrides.get(&id))
-        {
-            let i = name.find('\r');
-            (
-                name.replace('\r', " "),
-                i.map(|i| (i as u32 + 1, name.len() as u32)),
-            )
-        } else {
-            let extra = config
+        apps.insert(id, app);
+    }
+    apps
+}
+
+// create the entry of an app and the entries of its desktop actions
+fn load_app_entries(
+    id: &str,
+    app: &AppInfo,
+    config: &Config,
+    history: &History,
+) -> Vec<AppEntry> {
+    let mut entries = Vec::new();
+    let app = app.clone();
+    let overrides = config.app_override(&app);
+    let name = overrides
+        .and_then(|o| o.name.clone())
+        .unwrap_or_else(|| app.display_name().to_string());
+
+    let (display_string, extra_range) = if let Some(name) =
+        get_app_field(&app, Field::Id).and_then(|id| config.name_overrides.get(&id))
+    {
+        let i = name.find('\r');
+        (
+            name.replace('\r', " "),
+            i.map(|i| (i as u32 + 1, name.len() as u32)),
+        )
+    } else {
+        let extra_override = overrides.and_then(|o| o.extra.clone());
+        let overridden = extra_override.is_some();
+        let extra = extra_override.or_else(|| {
+            config
                 .extra_field
                 .get(0)
-                .and_then(|f| get_app_field(&app, *f));
-            match extra {
-                Some(e)
-                    if (!config.hide_extra_if_contained
-                        || !name.to_lowercase().contains(&e.to_lowercase())) =>
-                {
-                    (
-                        format!("{}{}{}",
-                            name,
-                            if config.extra_field_newline {"\n"} else {" "},
-                            e
-                        ),
-                        Some((
-                            name.len() as u32 + 1,
-                            name.len() as u32 + 1 + e.len() as u32,
-                        )),
-                    )
-                }
-                _ => (name, None),
+                .and_then(|f| get_app_field(&app, *f))
+        });
+        match extra {
+            Some(e)
+                if (overridden
+                    || !config.hide_extra_if_contained
+                    || !name.to_lowercase().contains(&e.to_lowercase())) =>
+            {
+                (
+                    format!("{}{}{}",
+                        name,
+                        if config.extra_field_newline {"\n"} else {" "},
+                        e
+                    ),
+                    Some((
+                        name.len() as u32 + 1,
+                        name.len() as u32 + 1 + e.len() as u32,
+                    )),
+                )
             }
-        };
+            _ => (name, None),
+        }
+    };
+
+    let mut search_fields: Vec<(String, f64)> = config
+        .hidden_fields
+        .iter()
+        .filter_map(|f| get_app_field(&app, *f).map(|value| (value, config.field_weight(*f))))
+        .collect();
+    if let Some(o) = overrides.filter(|o| !o.keywords.is_empty()) {
+        search_fields.push((o.keywords.join(" "), config.field_weight(Field::Keywords)));
+    }
 
-        let hidden = config
-            .hidden_fields
-            .iter()
-            .map(|f| get_app_field(&app, *f).unwrap_or_default())
-            .collect::<Vec<String>>()
-            .join(" ");
+    let icon = overrides
+        .and_then(|o| o.icon.as_ref())
+        .and_then(|icon| Icon::for_string(icon).ok())
+        .or_else(|| app.icon());
+
+    let desktop_info = app
+        .downcast_ref::<DesktopAppInfo>()
+        .filter(|_| config.desktop_actions);
+    if let Some(desktop_info) = desktop_info {
+        let base_name = get_app_field(&app, Field::Id)
+            .and_then(|id| config.name_overrides.get(&id))
+            .and_then(|name| name.split('\r').next())
+            .map(String::from)
+            .or_else(|| overrides.and_then(|o| o.name.clone()))
+            .unwrap_or_else(|| app.display_name().to_string());
+        for action in desktop_info.list_actions() {
+            let action_name = desktop_info.action_name(&action);
+            let display_string = format!("{}{}{}", base_name, ACTION_SEPARATOR, action_name);
+            let kind = EntryKind::Action(AppHandle::new(id, app.clone()), action.to_string());
+            entries.push(
+                AppEntry::new(display_string, None, search_fields.clone(), kind, icon.clone())
+                    .with_history(history, config),
+            );
+        }
+    }
 
-        let search_string = if hidden.is_empty() {
-            display_string.clone()
-        } else {
-            format!("{} {}", display_string, hidden)
-        };
+    let kind = EntryKind::App(AppHandle::new(id, app));
+    entries.push(
+        AppEntry::new(display_string, extra_range, search_fields, kind, icon)
+            .with_history(history, config),
+    );
+    entries
+}
 
-        let label = LabelBuilder::new()
-            .xalign(0.0f32)
-            .label(&display_string)
-            .wrap(true)
-            .ellipsize(EllipsizeMode::End)
-            .lines(config.lines)
-            .build();
-        label.style_context().add_class(APP_LABEL_CLASS);
-
-        let image = ImageBuilder::new().pixel_size(config.icon_size).build();
-        if let Some(icon) = app.icon() {
-            // Don't set the icon if it'd give us an ugly fallback icon
-            if icon_theme
-                .lookup_by_gicon(&icon, config.icon_size, IconLookupFlags::FORCE_SIZE)
-                .is_some()
-            {
-                image.set_from_gicon(&icon, gtk::IconSize::Menu);
-            }
+fn load_custom_entry(
+    custom: &CustomEntry,
+    config: &Config,
+    history: &History,
+) -> AppEntry {
+    let (display_string, extra_range) = match &custom.extra {
+        Some(extra) => {
+            let separator = if config.extra_field_newline { "\n" } else { " " };
+            (
+                format!("{}{}{}", custom.name, separator, extra),
+                Some((
+                    custom.name.len() as u32 + 1,
+                    custom.name.len() as u32 + 1 + extra.len() as u32,
+                )),
+            )
         }
-        image.style_context().add_class(APP_ICON_CLASS);
+        _ => (custom.name.clone(), None),
+    };
+    let search_fields = if custom.keywords.is_empty() {
+        Vec::new()
+    } else {
+        vec![(custom.keywords.join(" "), config.field_weight(Field::Keywords))]
+    };
+    let kind = EntryKind::Custom(custom.clone());
+    let icon = custom
+        .icon
+        .as_ref()
+        .and_then(|icon| Icon::for_string(icon).ok());
+    AppEntry::new(display_string, extra_range, search_fields, kind, icon)
+        .with_history(history, config)
+}
 
-        let hbox = BoxBuilder::new()
-            .orientation(Orientation::Horizontal)
-            .build();
-        hbox.pack_start(&image, false, false, 0);
-        hbox.pack_end(&label, true, true, 0);
+// modification time of the desktop file of an app, its entries are recreated when it changes
+fn desktop_file_modified(app: &AppInfo) -> Option<u64> {
+    let file = app.downcast_ref::<DesktopAppInfo>()?.filename()?;
+    modified(&file)
+}
 
-        let row = ListBoxRow::new();
-        row.add(&hbox);
-        row.style_context().add_class(APP_ROW_CLASS);
+// Update the entries after apps were installed, removed or changed.
+// Entries of unchanged apps are kept as they are. Returns whether entries were added or removed,
+// in which case the cache is updated as well.
+pub fn refresh_entries(entries: &mut Vec<AppEntry>, config: &Config, history: &History) -> bool {
+    let mut apps = load_apps(config);
+
+    let count = entries.len();
+    entries.retain(|e| match &e.kind {
+        EntryKind::App(app) | EntryKind::Action(app, _) => apps
+            .get(&app.id)
+            .is_some_and(|new| desktop_file_modified(new) == app.modified),
+        EntryKind::Custom(..) | EntryKind::Dmenu(..) => true,
+    });
+    let removed = entries.len() != count;
+
+    for e in entries.iter() {
+        if let EntryKind::App(app) = &e.kind {
+            apps.remove(&app.id);
+        }
+    }
 
-        let history_data = history.get(&id).copied().unwrap_or_default();
-        let last_used = if config.recent_first {
-            history_data.last_used
-        } else {
-            0
-        };
-        let usage_count = if config.frequent_first {
-            history_data.usage_count
-        } else {
-            0
-        };
+    let added = !apps.is_empty();
+    for (id, app) in apps {
+        entries.extend(load_app_entries(&id, &app, config, history));
+    }
+    if added || removed {
+        save_entry_cache(entries, config);
+    }
+    added || removed
+}
 
-        let app_entry = AppEntry {
-            display_string,
-            search_string,
-            extra_range,
-            info: app,
-            label,
-            score: 100,
-            history: HistoryData {
-                last_used,
-                usage_count,
-            },
-        };
-        app_entry.set_markup(config);
-        entries.insert(row, app_entry);
+fn entry_history(history: &History, id: &str, config: &Config) -> HistoryData {
+    let history_data = history.apps.get(id).copied().unwrap_or_default();
+    let last_used = if config.recent_first {
+        history_data.last_used
+    } else {
+        0
+    };
+    let usage_count = if config.frequent_first {
+        history_data.usage_count
+    } else {
+        0
+    };
+    let frecency = if config.ranking == Ranking::Frecency {
+        history_data.frecency_at(now(), config.frecency_half_life)
+    } else {
+        0.0
+    };
+    HistoryData {
+        last_used,
+        usage_count,
+        frecency,
+    }
+}
+
+pub fn load_dmenu_entries(lines: &[Vec<u8>], config: &Config) -> Vec<AppEntry> {
+    let mut entries = Vec::with_capacity(lines.len());
+
+    for (i, bytes) in lines.iter().enumerate() {
+        // invalid UTF-8 is only replaced in what is displayed
+        let line = String::from_utf8_lossy(bytes);
+        // like in name_overrides, everything after a tab is displayed as extra text
+        let display_string = line.replacen('\t', " ", 1);
+        let extra_range = line
+            .find('\t')
+            .map(|i| (i as u32 + 1, line.len() as u32));
+
+        let kind = EntryKind::Dmenu(i, bytes.clone());
+        let mut app_entry = AppEntry::new(display_string, extra_range, Vec::new(), kind, None);
+        app_entry.update_score(config);
+        entries.push(app_entry);
     }
     entries
 }
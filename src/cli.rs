@@ -0,0 +1,185 @@
+/*
+Copyright (C) 2020 Dorian Rudolph
+
+sirula is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+sirula is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with sirula.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use super::history::{history_to_string, load_history, modify_history, parse_history, History};
+use std::io::{Read, Write};
+
+const USAGE: &str = "\
+Usage: sirula [OPTIONS]
+       sirula history COMMAND
+
+Options:
+  --dmenu          read items from stdin and print the chosen one to stdout
+  --daemon         keep running in the background, running sirula again shows the window
+  --rebuild-cache  load all apps from their desktop files instead of the cache
+  --timing         print how long loading the apps took
+  -h, --help       print this help
+
+History commands:
+  list [--json]   print the launch count and last use of every entry
+  forget ID       remove an entry from the history
+  clear           remove all entries from the history, pinned entries stay pinned
+  export [FILE]   write the history file to FILE or stdout
+  import [FILE]   replace the history with the one read from FILE or stdin
+";
+
+pub enum HistoryCommand {
+    List { json: bool },
+    Forget(String),
+    Clear,
+    Export(Option<String>),
+    Import(Option<String>),
+}
+
+#[derive(Default)]
+pub struct Args {
+    pub dmenu: bool,
+    pub daemon: bool,
+    pub rebuild_cache: bool,
+    pub timing: bool,
+    // run a history command instead of showing the launcher
+    pub history: Option<HistoryCommand>,
+    // arguments we do not know are passed on to gtk
+    pub gtk_args: Vec<String>,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    std::process::exit(2);
+}
+
+fn parse_history_command(args: &[String]) -> HistoryCommand {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["list"] => HistoryCommand::List { json: false },
+        ["list", "--json"] => HistoryCommand::List { json: true },
+        ["forget", id] => HistoryCommand::Forget(id.to_string()),
+        ["clear"] => HistoryCommand::Clear,
+        ["export"] => HistoryCommand::Export(None),
+        ["export", file] => HistoryCommand::Export(Some(file.to_string())),
+        ["import"] => HistoryCommand::Import(None),
+        ["import", file] => HistoryCommand::Import(Some(file.to_string())),
+        _ => usage_error("Invalid history command"),
+    }
+}
+
+impl Args {
+    pub fn parse() -> Args {
+        let mut args = std::env::args();
+        let mut parsed = Args {
+            gtk_args: args.next().into_iter().collect(),
+            ..Default::default()
+        };
+        let args: Vec<String> = args.collect();
+        if args.first().map(String::as_str) == Some("history") {
+            parsed.history = Some(parse_history_command(&args[1..]));
+            return parsed;
+        }
+        for arg in args {
+            match arg.as_str() {
+                "--dmenu" => parsed.dmenu = true,
+                "--daemon" => parsed.daemon = true,
+                "--rebuild-cache" => parsed.rebuild_cache = true,
+                "--timing" => parsed.timing = true,
+                "-h" | "--help" => {
+                    print!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => parsed.gtk_args.push(arg),
+            }
+        }
+        if parsed.dmenu && parsed.daemon {
+            eprintln!("--dmenu cannot be combined with --daemon");
+            std::process::exit(2);
+        }
+        parsed
+    }
+}
+
+fn list_history(json: bool) -> Result<(), String> {
+    let history = load_history(0);
+    if json {
+        let s = serde_json::to_string_pretty(&history.apps).map_err(|err| err.to_string())?;
+        println!("{}", s);
+    } else {
+        let mut apps: Vec<_> = history.apps.iter().collect();
+        apps.sort_by(|(a_id, a), (b_id, b)| {
+            b.usage_count.cmp(&a.usage_count).then_with(|| a_id.cmp(b_id))
+        });
+        for (id, data) in apps {
+            println!("{}\t{}\t{}", id, data.usage_count, data.last_used);
+        }
+    }
+    Ok(())
+}
+
+fn export_history(file: Option<&str>) -> Result<(), String> {
+    let s = history_to_string(&load_history(0))?;
+    match file {
+        Some(file) => std::fs::write(file, s),
+        None => std::io::stdout().write_all(s.as_bytes()),
+    }
+    .map_err(|err| err.to_string())
+}
+
+fn import_history(file: Option<&str>) -> Result<(), String> {
+    let s = match file {
+        Some(file) => std::fs::read_to_string(file),
+        None => {
+            let mut s = String::new();
+            std::io::stdin().read_to_string(&mut s).map(|_| s)
+        }
+    }
+    .map_err(|err| err.to_string())?;
+    let imported = parse_history(&s).map_err(|err| err.to_string())?;
+    modify_history(&mut History::default(), 0, |h| *h = imported);
+    Ok(())
+}
+
+// run a history command and return the exit status
+pub fn run_history_command(command: HistoryCommand) -> i32 {
+    let result = match command {
+        HistoryCommand::List { json } => list_history(json),
+        HistoryCommand::Forget(id) => {
+            let mut found = false;
+            modify_history(&mut History::default(), 0, |h| found = h.forget(&id));
+            if found {
+                Ok(())
+            } else {
+                Err(format!("No history for {}", id))
+            }
+        }
+        HistoryCommand::Clear => {
+            modify_history(&mut History::default(), 0, |h| {
+                *h = History {
+                    pinned: std::mem::take(&mut h.pinned),
+                    ..Default::default()
+                }
+            });
+            Ok(())
+        }
+        HistoryCommand::Export(file) => export_history(file.as_deref()),
+        HistoryCommand::Import(file) => import_history(file.as_deref()),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
@@ -16,9 +16,15 @@ along with sirula.  If not, see <https://www.gnu.org/licenses/>.
 */
 
 use super::consts::*;
+use super::matcher::MatchMode;
 use super::util::get_config_file;
+use gdk::{keys::constants, ModifierType};
+use gio::{prelude::AppInfoExt, AppInfo};
 use pango::Attribute;
-use serde::{de::Error, Deserializer};
+use serde::{
+    de::{value, Error, IntoDeserializer},
+    Deserializer,
+};
 use serde_derive::Deserialize;
 use std::collections::HashMap;
 
@@ -34,7 +40,7 @@ macro_rules! make_config {
     };
 }
 
-#[derive(Deserialize, Debug, Copy, Clone)]
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[serde(rename_all = "snake_case")]
 pub enum Field {
     Comment,
@@ -42,8 +48,92 @@ pub enum Field {
     IdSuffix,
     Executable,
     Commandline,
+    Keywords,
+    GenericName,
+    Categories,
+    // the untranslated name
+    Name,
+    DesktopFilePath,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum KeyAction {
+    SelectNext,
+    SelectPrev,
+    PageDown,
+    PageUp,
+    Launch,
+    LaunchInTerminal,
+    Close,
+    ClearQuery,
+    DeleteHistoryEntry,
+    TogglePin,
+    CycleMatchMode,
+}
+
+impl KeyAction {
+    fn parse(name: &str) -> Option<KeyAction> {
+        Some(match name {
+            "select-next" => KeyAction::SelectNext,
+            "select-prev" => KeyAction::SelectPrev,
+            "page-down" => KeyAction::PageDown,
+            "page-up" => KeyAction::PageUp,
+            "launch" => KeyAction::Launch,
+            "launch-in-terminal" => KeyAction::LaunchInTerminal,
+            "close" => KeyAction::Close,
+            "clear-query" => KeyAction::ClearQuery,
+            "delete-history-entry" => KeyAction::DeleteHistoryEntry,
+            "toggle-pin" => KeyAction::TogglePin,
+            "cycle-match-mode" => KeyAction::CycleMatchMode,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Ranking {
+    Usage,
+    Frecency,
+}
+
+// launchable item defined in the config instead of a desktop file
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CustomEntry {
+    pub name: String,
+    pub extra: Option<String>,
+    // icon name or path
+    pub icon: Option<String>,
+    pub command: String,
+    #[serde(default)]
+    pub terminal: bool,
+    // additional words to search for, not displayed
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
+// settings of a single app in [app."<id>"], overriding the desktop file and the global settings
+#[derive(Deserialize, Debug, Default)]
+pub struct AppOverride {
+    pub name: Option<String>,
+    pub extra: Option<String>,
+    // icon name or path
+    pub icon: Option<String>,
+    // additional words to search for, not displayed
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    pub terminal: Option<bool>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    // appended to the command line
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub cgroups: Option<bool>,
+}
+
+// maps key value and modifiers to actions
+pub type Keybindings = HashMap<(u32, ModifierType), KeyAction>;
+
 // not sure how to avoid having to specify the name twice
 make_config!(Config {
     markup_default: Vec<Attribute> = (Vec::new()) "markup_default" [deserialize_with = "deserialize_markup"],
@@ -52,6 +142,12 @@ make_config!(Config {
     exclusive: bool = (true) "exclusive",
     frequent_first: bool = (false) "frequent_first",
     recent_first: bool = (true) "recent_first",
+    ranking: Ranking = (Ranking::Usage) "ranking",
+    frecency_half_life: f64 = (7.0) "frecency_half_life" [deserialize_with = "deserialize_half_life"],
+    history_weight: f64 = (0.0) "history_weight",
+    query_weight: f64 = (20.0) "query_weight",
+    pinned_boost: i64 = (50) "pinned_boost",
+    debug_scores: bool = (false) "debug_scores",
     prune_history: u32 = (0) "prune_history",
     icon_size: i32 = (64) "icon_size",
     lines: i32 = (2) "lines",
@@ -68,13 +164,21 @@ make_config!(Config {
     extra_field: Vec<Field> = (vec![Field::IdSuffix]) "extra_field",
     extra_field_newline: bool = (false) "extra_field_newline",
     hidden_fields: Vec<Field> = (Vec::new()) "hidden_fields",
+    match_mode: MatchMode = (MatchMode::Fuzzy) "match_mode",
+    acronym_boost: i64 = (100) "acronym_boost",
+    max_typos: usize = (2) "max_typos",
+    field_weights: HashMap<Field, f64> = (default_field_weights()) "field_weights" [deserialize_with = "deserialize_field_weights"],
     name_overrides: HashMap<String, String> = (HashMap::new()) "name_overrides",
+    app_overrides: HashMap<String, AppOverride> = (HashMap::new()) "app_overrides" [rename = "app"],
     hide_extra_if_contained: bool = (true) "hide_extra_if_contained",
     cgroups: bool = (true) "cgroups",
     command_prefix: String = (":".into()) "command_prefix",
     exclude: Vec<String> = (Vec::new()) "exclude",
     term_command: Option<String> = (None) "term_command",
-    close_on_unfocus: bool = (true) "close_on_unfocus"
+    close_on_unfocus: bool = (true) "close_on_unfocus",
+    desktop_actions: bool = (false) "desktop_actions",
+    custom_entries: Vec<CustomEntry> = (Vec::new()) "custom_entries" [rename = "entry"],
+    keybindings: Keybindings = (default_keybindings()) "keybindings" [deserialize_with = "deserialize_keybindings"]
 });
 
 fn deserialize_markup<'de, D>(deserializer: D) -> Result<Vec<Attribute>, D::Error>
@@ -85,7 +189,108 @@ where
     parse_attributes(s).map_err(D::Error::custom)
 }
 
+// the history weight of a launch halves after this many days, so it must be positive
+fn deserialize_half_life<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let days: f64 = serde::Deserialize::deserialize(deserializer)?;
+    if days > 0.0 {
+        Ok(days)
+    } else {
+        Err(D::Error::custom(format!(
+            "Invalid frecency_half_life: {} is not a positive number of days",
+            days
+        )))
+    }
+}
+
+fn default_field_weights() -> HashMap<Field, f64> {
+    let mut weights = HashMap::new();
+    weights.insert(Field::GenericName, 0.8);
+    weights.insert(Field::Keywords, 0.6);
+    weights.insert(Field::Comment, 0.3);
+    weights
+}
+
+// toml cannot deserialize enums as keys, so we parse them ourselves
+fn deserialize_field_weights<'de, D>(deserializer: D) -> Result<HashMap<Field, f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let map: HashMap<String, f64> = serde::Deserialize::deserialize(deserializer)?;
+    let mut weights = default_field_weights();
+    let mut errors = Vec::new();
+    for (name, weight) in map {
+        let field: Result<Field, value::Error> =
+            serde::Deserialize::deserialize(name.as_str().into_deserializer());
+        match field {
+            Ok(field) => {
+                weights.insert(field, weight);
+            }
+            Err(_) => errors.push(format!("unknown field \"{}\"", name)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(weights)
+    } else {
+        errors.sort();
+        Err(D::Error::custom(format!("Invalid field weights: {}", errors.join(", "))))
+    }
+}
+
+fn default_keybindings() -> Keybindings {
+    let mut keybindings = HashMap::new();
+    keybindings.insert((*constants::Escape, ModifierType::empty()), KeyAction::Close);
+    keybindings.insert(
+        (*constants::Delete, ModifierType::SHIFT_MASK),
+        KeyAction::DeleteHistoryEntry,
+    );
+    keybindings.insert((*constants::p, ModifierType::CONTROL_MASK), KeyAction::TogglePin);
+    keybindings.insert((*constants::m, ModifierType::CONTROL_MASK), KeyAction::CycleMatchMode);
+    keybindings
+}
+
+fn deserialize_keybindings<'de, D>(deserializer: D) -> Result<Keybindings, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let map: HashMap<String, String> = serde::Deserialize::deserialize(deserializer)?;
+    let mut keybindings = default_keybindings();
+    let mut errors = Vec::new();
+    for (accelerator, action) in map {
+        let (key, modifiers) = gtk::accelerator_parse(&accelerator);
+        if key == 0 {
+            errors.push(format!("cannot parse accelerator \"{}\"", accelerator));
+        }
+        match KeyAction::parse(&action) {
+            Some(action) if key != 0 => {
+                keybindings.insert((key, modifiers), action);
+            }
+            Some(_) => (),
+            None => errors.push(format!("unknown action \"{}\"", action)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(keybindings)
+    } else {
+        errors.sort();
+        Err(D::Error::custom(format!("Invalid keybindings: {}", errors.join(", "))))
+    }
+}
+
 impl Config {
+    // weight of matches in a hidden field relative to matches in the displayed string
+    pub fn field_weight(&self, field: Field) -> f64 {
+        self.field_weights.get(&field).copied().unwrap_or(1.0)
+    }
+
+    // the [app."<id>"] settings of an app, the id is the name of the desktop file without .desktop
+    pub fn app_override(&self, info: &AppInfo) -> Option<&AppOverride> {
+        let id = info.id()?;
+        self.app_overrides.get(id.strip_suffix(".desktop").unwrap_or(id.as_str()))
+    }
+
     pub fn load() -> Config {
         let config_str = match get_config_file(CONFIG_FILE) {
             Some(file) => std::fs::read_to_string(file).expect("Cannot read config"),
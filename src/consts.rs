@@ -21,11 +21,27 @@ pub const APP_NAME: &str = "sirula";
 pub const STYLE_FILE: &str = "style.css";
 pub const CONFIG_FILE: &str = "config.toml";
 pub const HISTORY_FILE: &str = "history";
+pub const ENTRY_CACHE_FILE: &str = "entries.json";
 
 pub const APP_LABEL_CLASS: &str = "app-label";
+pub const APP_LABEL_TYPO_CLASS: &str = "app-label-typo";
 pub const APP_ICON_CLASS: &str = "app-icon";
 pub const APP_ROW_CLASS: &str = "app-row";
+pub const APP_ROW_PINNED_CLASS: &str = "app-row-pinned";
 pub const ROOT_BOX_NAME: &str = "root-box";
 pub const LISTBOX_NAME: &str = "app-list";
 pub const SEARCH_ENTRY_NAME: &str = "search";
 pub const SCROLL_NAME: &str = "scroll";
+
+pub const ACTION_SEPARATOR: &str = " › ";
+
+// number of entries matched at once before giving gtk the chance to handle events
+pub const MATCH_CHUNK_SIZE: usize = 1000;
+
+// number of rows created before the height of the list is known
+pub const LIST_INITIAL_ROWS: usize = 20;
+
+// exit codes in dmenu mode
+pub const EXIT_SELECTED: i32 = 0;
+pub const EXIT_CANCELLED: i32 = 1;
+pub const EXIT_CUSTOM: i32 = 2;
@@ -0,0 +1,137 @@
+/*
+Copyright (C) 2020 Dorian Rudolph
+
+sirula is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+sirula is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with sirula.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+// The app entries are cached, so that startup does not need to load every desktop file and look
+// up every icon. The cache is only used while everything it was built from is unchanged.
+
+use super::{consts::*, util::get_cache_file, util::get_config_file};
+use glib::ObjectExt;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+const ENTRY_CACHE_VERSION: u32 = 2;
+
+// everything the cached entries depend on
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct CacheKey {
+    version: u32,
+    // modification times of the desktop files and the config file
+    files: BTreeMap<String, u64>,
+    icon_theme: Option<String>,
+    // translated names are used for the preferred languages
+    languages: Vec<String>,
+    // apps can be shown only in certain desktops
+    current_desktop: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CachedEntry {
+    // desktop file id of the app
+    pub id: String,
+    pub action: Option<String>,
+    // modification time of the desktop file, to find out whether the app changed later
+    pub modified: Option<u64>,
+    pub display_string: String,
+    pub extra_range: Option<(u32, u32)>,
+    pub search_fields: Vec<(String, f64)>,
+    // file of the icon in the icon theme, None if there is no icon
+    pub icon: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EntryCache {
+    key: CacheKey,
+    entries: Vec<CachedEntry>,
+}
+
+pub fn modified(path: &Path) -> Option<u64> {
+    let time = path.metadata().ok()?.modified().ok()?;
+    time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
+
+// add the modification times of all desktop files in dir and its subdirectories
+fn add_desktop_files(dir: &Path, files: &mut BTreeMap<String, u64>) {
+    let read_dir = match dir.read_dir() {
+        Ok(read_dir) => read_dir,
+        _ => return,
+    };
+    for entry in read_dir.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            add_desktop_files(&path, files);
+        } else if path.extension().is_some_and(|e| e == "desktop") {
+            if let Some(time) = modified(&path) {
+                files.insert(path.to_string_lossy().to_string(), time);
+            }
+        }
+    }
+}
+
+pub fn cache_key() -> CacheKey {
+    let mut files = BTreeMap::new();
+    let data_dirs = std::iter::once(glib::user_data_dir()).chain(glib::system_data_dirs());
+    for dir in data_dirs {
+        add_desktop_files(&dir.join("applications"), &mut files);
+    }
+    if let Some(config) = get_config_file(CONFIG_FILE) {
+        if let Some(time) = modified(&config) {
+            files.insert(config.to_string_lossy().to_string(), time);
+        }
+    }
+
+    CacheKey {
+        version: ENTRY_CACHE_VERSION,
+        files,
+        icon_theme: gtk::Settings::default()
+            .and_then(|s| s.property::<Option<String>>("gtk-icon-theme-name")),
+        languages: glib::language_names().iter().map(|l| l.to_string()).collect(),
+        current_desktop: std::env::var("XDG_CURRENT_DESKTOP").ok(),
+    }
+}
+
+// the cached entries, None if there are none or they were built from something else
+pub fn read_entry_cache(key: &CacheKey) -> Option<Vec<CachedEntry>> {
+    let file = get_cache_file(ENTRY_CACHE_FILE, false)?;
+    let s = std::fs::read_to_string(file).ok()?;
+    let cache: EntryCache = match serde_json::from_str(&s) {
+        Ok(cache) => cache,
+        Err(err) => {
+            eprintln!("Cannot parse entry cache: {}", err);
+            return None;
+        }
+    };
+    if cache.key == *key {
+        Some(cache.entries)
+    } else {
+        None
+    }
+}
+
+pub fn write_entry_cache(key: CacheKey, entries: Vec<CachedEntry>) -> Result<(), String> {
+    let file = get_cache_file(ENTRY_CACHE_FILE, true).ok_or("Cannot create cache directory")?;
+    let s = serde_json::to_string(&EntryCache { key, entries }).map_err(|err| err.to_string())?;
+
+    // replace the file at once, another instance may read it at the same time
+    let tmp_file = file.with_extension("tmp");
+    let mut tmp = File::create(&tmp_file).map_err(|err| err.to_string())?;
+    tmp.write_all(s.as_bytes()).map_err(|err| err.to_string())?;
+    std::fs::rename(&tmp_file, &file).map_err(|err| err.to_string())
+}
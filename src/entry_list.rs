@@ -0,0 +1,361 @@
+/*
+Copyright (C) 2020 Dorian Rudolph
+
+sirula is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+sirula is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with sirula.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use super::{app_entry::AppEntry, consts::*, Config};
+use crate::clone;
+use gtk::{
+    builders::{BoxBuilder, ImageBuilder, LabelBuilder, ListBoxBuilder, ScrolledWindowBuilder},
+    prelude::*,
+    Adjustment, IconLookupFlags, IconTheme, Image, Label, ListBox, ListBoxRow, Orientation,
+    Scrollbar,
+};
+use pango::EllipsizeMode;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+// widgets of a row, which show whatever entry is scrolled to their position
+struct RowWidgets {
+    row: ListBoxRow,
+    image: Option<Image>,
+    label: Label,
+}
+
+impl RowWidgets {
+    fn new(config: &Config, icons: bool) -> RowWidgets {
+        let label = LabelBuilder::new()
+            .xalign(0.0f32)
+            .wrap(true)
+            .ellipsize(EllipsizeMode::End)
+            .lines(config.lines)
+            .build();
+        label.style_context().add_class(APP_LABEL_CLASS);
+        // every row is as high as the highest one could be, so that the rows that fit into the
+        // window can be computed from the height of the first one
+        let lines = vec!["X"; config.lines.max(1) as usize].join("\n");
+        label.connect_style_updated(move |label| {
+            let (_, height) = label.create_pango_layout(Some(&lines)).pixel_size();
+            label.set_size_request(-1, height);
+        });
+
+        let hbox = BoxBuilder::new()
+            .orientation(Orientation::Horizontal)
+            .build();
+        let image = icons.then(|| {
+            let image = ImageBuilder::new().pixel_size(config.icon_size).build();
+            image.style_context().add_class(APP_ICON_CLASS);
+            hbox.pack_start(&image, false, false, 0);
+            image
+        });
+        hbox.pack_end(&label, true, true, 0);
+        hbox.show_all();
+
+        let row = ListBoxRow::new();
+        row.add(&hbox);
+        row.set_can_focus(false);
+        // rows without an entry stay hidden when the window is shown
+        row.set_no_show_all(true);
+        row.style_context().add_class(APP_ROW_CLASS);
+        RowWidgets { row, image, label }
+    }
+
+    fn show(&self, entry: &AppEntry, icon_theme: &IconTheme, config: &Config) {
+        self.label.set_text(&entry.display_string);
+        self.label.set_attributes(Some(&entry.markup(config)));
+        self.label.set_tooltip_text(entry.tooltip(config).as_deref());
+        set_class(&self.label, APP_LABEL_TYPO_CLASS, entry.typo);
+        set_class(&self.row, APP_ROW_PINNED_CLASS, entry.pinned);
+        if let Some(image) = &self.image {
+            // Don't set the icon if it'd give us an ugly fallback icon
+            let icon = entry.icon.as_ref().filter(|icon| {
+                icon_theme
+                    .lookup_by_gicon(*icon, config.icon_size, IconLookupFlags::FORCE_SIZE)
+                    .is_some()
+            });
+            match icon {
+                Some(icon) => image.set_from_gicon(icon, gtk::IconSize::Menu),
+                None => image.clear(),
+            }
+        }
+        self.row.show();
+    }
+}
+
+fn set_class(widget: &impl IsA<gtk::Widget>, class: &str, set: bool) {
+    if set {
+        widget.style_context().add_class(class);
+    } else {
+        widget.style_context().remove_class(class);
+    }
+}
+
+// List of the matching entries that only has widgets for the rows that fit into the window.
+// Scrolling does not move the rows, but shows other entries in them, so that lists with tens of
+// thousands of entries (e.g., in dmenu mode) do not need as many widgets.
+pub struct EntryList {
+    widget: gtk::Box,
+    listbox: ListBox,
+    scrollbar: Scrollbar,
+    // position of the first shown entry, the page size is the number of rows that fit
+    adjustment: Adjustment,
+    rows: RefCell<Vec<RowWidgets>>,
+    // number of rows needed to fill the window
+    wanted_rows: Cell<usize>,
+    // indices of the matching entries, in the order they are displayed
+    order: RefCell<Vec<usize>>,
+    // position of the selected entry in order
+    selected: Cell<Option<usize>>,
+    entries: Rc<RefCell<Vec<AppEntry>>>,
+    icon_theme: IconTheme,
+    icons: bool,
+    config: Rc<Config>,
+}
+
+impl EntryList {
+    pub fn new(
+        entries: Rc<RefCell<Vec<AppEntry>>>,
+        config: Rc<Config>,
+        icons: bool,
+    ) -> Rc<EntryList> {
+        let listbox = ListBoxBuilder::new()
+            .name(LISTBOX_NAME)
+            .can_focus(false)
+            .build();
+        let scroll = ScrolledWindowBuilder::new()
+            .name(SCROLL_NAME)
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            // rows that do not fit are cut off, the scrollbar below moves entries through the rows
+            .vscrollbar_policy(gtk::PolicyType::External)
+            .build();
+        scroll.add(&listbox);
+
+        let adjustment = Adjustment::new(0.0, 0.0, 0.0, 1.0, 1.0, 1.0);
+        let scrollbar = Scrollbar::new(Orientation::Vertical, Some(&adjustment));
+        scrollbar.set_no_show_all(true);
+
+        let widget = BoxBuilder::new()
+            .orientation(Orientation::Horizontal)
+            .build();
+        widget.pack_start(&scroll, true, true, 0);
+        widget.pack_end(&scrollbar, false, false, 0);
+
+        let list = Rc::new(EntryList {
+            widget,
+            listbox,
+            scrollbar,
+            adjustment,
+            rows: RefCell::new(Vec::new()),
+            wanted_rows: Cell::new(LIST_INITIAL_ROWS),
+            order: RefCell::new(Vec::new()),
+            selected: Cell::new(None),
+            entries,
+            icon_theme: IconTheme::default().unwrap(),
+            icons,
+            config,
+        });
+        list.add_rows();
+
+        list.adjustment.connect_value_changed(clone!(list => move |_| list.bind()));
+
+        list.listbox.connect_row_selected(clone!(list => move |_, row| {
+            if let Some(row) = row {
+                list.selected.set(Some(list.offset() + row.index() as usize));
+            }
+        }));
+
+        scroll.connect_scroll_event(clone!(list => move |_, event| {
+            let delta = match event.direction() {
+                gdk::ScrollDirection::Up => -1.0,
+                gdk::ScrollDirection::Down => 1.0,
+                gdk::ScrollDirection::Smooth => event.delta().1,
+                _ => 0.0,
+            };
+            list.adjustment.set_value(list.adjustment.value() + delta);
+            Inhibit(true)
+        }));
+
+        scroll.connect_size_allocate(clone!(list => move |_, alloc| list.resize(alloc.height())));
+
+        list
+    }
+
+    pub fn widget(&self) -> &gtk::Box {
+        &self.widget
+    }
+
+    // call f with the index of the entry whose row was clicked
+    pub fn connect_activated<F: Fn(usize) + 'static>(self: &Rc<Self>, f: F) {
+        let list = self.clone();
+        self.listbox.connect_row_activated(move |_, row| {
+            let position = list.offset() + row.index() as usize;
+            let i = list.order.borrow().get(position).copied();
+            if let Some(i) = i {
+                f(i);
+            }
+        });
+    }
+
+    // index of the selected entry
+    pub fn selected_entry(&self) -> Option<usize> {
+        self.selected
+            .get()
+            .and_then(|p| self.order.borrow().get(p).copied())
+    }
+
+    // number of rows that fit into the window
+    pub fn page_size(&self) -> i32 {
+        (self.adjustment.page_size() as i32).max(1)
+    }
+
+    // show the entries that match now, select the first one and scroll to the top
+    pub fn update(&self) {
+        self.sort();
+        let empty = self.order.borrow().is_empty();
+        self.selected.set(if empty { None } else { Some(0) });
+        self.adjustment.set_value(0.0);
+        self.bind();
+    }
+
+    // sort again after scores changed, the selected entry stays selected
+    pub fn resort(&self) {
+        let selected = self.selected_entry();
+        self.sort();
+        let position = selected.and_then(|e| self.order.borrow().iter().position(|&i| i == e));
+        self.selected.set(position);
+        if let Some(position) = position {
+            self.scroll_to(position);
+        }
+        self.bind();
+    }
+
+    // select the entry with the given index and scroll it into view, if it is shown
+    pub fn select_entry(&self, entry: usize) {
+        let position = self.order.borrow().iter().position(|&i| i == entry);
+        if let Some(position) = position {
+            self.selected.set(Some(position));
+            self.scroll_to(position);
+            self.bind();
+        }
+    }
+
+    // move the selection by the given number of entries and scroll it into view
+    pub fn move_selection(&self, delta: i32) {
+        let len = self.order.borrow().len();
+        if len == 0 {
+            return;
+        }
+        let position = match self.selected.get() {
+            Some(p) => (p as i64 + delta as i64).clamp(0, len as i64 - 1) as usize,
+            None => 0,
+        };
+        self.selected.set(Some(position));
+        self.scroll_to(position);
+        self.bind();
+    }
+
+    fn offset(&self) -> usize {
+        self.adjustment.value() as usize
+    }
+
+    fn sort(&self) {
+        let order = {
+            let entries = self.entries.borrow();
+            let mut order: Vec<usize> = (0..entries.len())
+                .filter(|&i| !entries[i].hidden())
+                .collect();
+            order.sort_by(|&a, &b| entries[a].cmp(&entries[b]));
+            order
+        };
+        let len = order.len() as f64;
+        *self.order.borrow_mut() = order;
+        self.adjustment.set_upper(len);
+        self.scrollbar.set_visible(len > self.adjustment.page_size());
+    }
+
+    fn scroll_to(&self, position: usize) {
+        let offset = self.offset();
+        let page = self.page_size() as usize;
+        if position < offset {
+            self.adjustment.set_value(position as f64);
+        } else if position >= offset + page {
+            self.adjustment.set_value((position + 1 - page) as f64);
+        }
+    }
+
+    // show the entries at the current scroll position in the rows
+    fn bind(&self) {
+        let entries = self.entries.borrow();
+        let order = self.order.borrow();
+        let offset = self.offset();
+        let mut selected_row = None;
+        for (i, widgets) in self.rows.borrow().iter().enumerate() {
+            match order.get(offset + i).and_then(|&e| entries.get(e)) {
+                Some(entry) => {
+                    widgets.show(entry, &self.icon_theme, &self.config);
+                    if self.selected.get() == Some(offset + i) {
+                        selected_row = Some(widgets.row.clone());
+                    }
+                }
+                None => widgets.row.hide(),
+            }
+        }
+        match selected_row {
+            Some(row) => self.listbox.select_row(Some(&row)),
+            None => self.listbox.unselect_all(),
+        }
+    }
+
+    // adapt the page size and the number of rows to the height of the list
+    fn resize(self: &Rc<Self>, height: i32) {
+        let row_height = self
+            .rows
+            .borrow()
+            .first()
+            .map_or(0, |r| r.row.allocated_height());
+        if row_height <= 0 {
+            return;
+        }
+        let page = (height / row_height).max(1) as f64;
+        if page != self.adjustment.page_size() {
+            self.adjustment.set_page_size(page);
+            self.adjustment.set_page_increment(page);
+            self.scrollbar
+                .set_visible(self.order.borrow().len() as f64 > page);
+        }
+
+        // one more row for the one that is only partially visible
+        let wanted = page as usize + 1;
+        if wanted > self.wanted_rows.get() {
+            self.wanted_rows.set(wanted);
+            // widgets cannot be added while gtk allocates their sizes
+            let list = self.clone();
+            glib::idle_add_local(move || {
+                list.add_rows();
+                list.bind();
+                glib::Continue(false)
+            });
+        }
+    }
+
+    fn add_rows(&self) {
+        let mut rows = self.rows.borrow_mut();
+        while rows.len() < self.wanted_rows.get() {
+            let widgets = RowWidgets::new(&self.config, self.icons);
+            self.listbox.add(&widgets.row);
+            rows.push(widgets);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+/*
+Copyright (C) 2020 Dorian Rudolph
+
+sirula is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+sirula is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with sirula.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+// Parsing of the Exec key, see
+// https://specifications.freedesktop.org/desktop-entry-spec/latest/exec-variables.html
+
+enum Token {
+    Text(String),
+    Code(char),
+}
+
+// an argument of the command line and whether (parts of) it were quoted
+type Arg = (Vec<Token>, bool);
+
+const FIELD_CODES: &str = "fFuUick";
+const DEPRECATED_FIELD_CODES: &str = "dDnNvm";
+
+fn push_char(arg: &mut Arg, c: char) {
+    match arg.0.last_mut() {
+        Some(Token::Text(s)) => s.push(c),
+        _ => arg.0.push(Token::Text(c.to_string())),
+    }
+}
+
+fn push_code(arg: &mut Arg, code: Option<char>) -> Result<(), String> {
+    match code {
+        Some('%') => push_char(arg, '%'),
+        Some(c) if FIELD_CODES.contains(c) || DEPRECATED_FIELD_CODES.contains(c) => {
+            arg.0.push(Token::Code(c))
+        }
+        Some(c) => return Err(format!("Invalid field code %{}", c)),
+        None => return Err("Command line ends with a single %".into()),
+    }
+    Ok(())
+}
+
+fn split_exec(exec: &str) -> Result<Vec<Arg>, String> {
+    let mut args = Vec::new();
+    let mut arg: Option<Arg> = None;
+    let mut chars = exec.chars();
+
+    while let Some(c) = chars.next() {
+        if c == ' ' || c == '\t' || c == '\n' {
+            args.extend(arg.take());
+            continue;
+        }
+        let arg = arg.get_or_insert_with(Default::default);
+        match c {
+            '"' => {
+                arg.1 = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '`' | '$' | '\\')) => push_char(arg, c),
+                            Some(c) => {
+                                push_char(arg, '\\');
+                                push_char(arg, c);
+                            }
+                            None => return Err("Unterminated quote".into()),
+                        },
+                        Some('%') => push_code(arg, chars.next())?,
+                        Some(c) => push_char(arg, c),
+                        None => return Err("Unterminated quote".into()),
+                    }
+                }
+            }
+            // not allowed by the spec, but common enough that we accept shell-like single quotes
+            '\'' => {
+                arg.1 = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => push_char(arg, c),
+                        None => return Err("Unterminated quote".into()),
+                    }
+                }
+            }
+            '\\' => push_char(arg, chars.next().unwrap_or('\\')),
+            '%' => push_code(arg, chars.next())?,
+            c => push_char(arg, c),
+        }
+    }
+    args.extend(arg);
+    Ok(args)
+}
+
+// Expand an Exec value into the arguments of the command.
+// Sirula never passes files or URLs, so %f, %F, %u and %U expand to nothing.
+// Deprecated field codes are removed as required by the spec.
+pub fn expand_exec(
+    exec: &str,
+    icon: Option<&str>,
+    name: &str,
+    desktop_file: Option<&str>,
+) -> Result<Vec<String>, String> {
+    let mut command = Vec::new();
+
+    for (tokens, quoted) in split_exec(exec)? {
+        // field codes that make up a whole argument may expand to zero or multiple arguments
+        if let (false, [Token::Code(c)]) = (quoted, tokens.as_slice()) {
+            match c {
+                'i' => {
+                    if let Some(icon) = icon.filter(|i| !i.is_empty()) {
+                        command.push("--icon".into());
+                        command.push(icon.into());
+                    }
+                }
+                'c' => command.push(name.into()),
+                'k' => command.extend(desktop_file.map(String::from)),
+                _ => {}
+            }
+            continue;
+        }
+
+        let mut arg = String::new();
+        for token in tokens {
+            match token {
+                Token::Text(s) => arg.push_str(&s),
+                Token::Code('c') => arg.push_str(name),
+                Token::Code('k') => arg.push_str(desktop_file.unwrap_or_default()),
+                Token::Code('i') => return Err("%i must be a separate argument".into()),
+                Token::Code(_) => {}
+            }
+        }
+        command.push(arg);
+    }
+
+    if command.is_empty() {
+        Err("Empty command line".into())
+    } else {
+        Ok(command)
+    }
+}
+
+// Undo the escape sequences of string values (\s, \n, \t, \r and \\).
+// GKeyFile already does this for the values gio gives us, but not freedesktop_entry_parser.
+pub fn unescape_string(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => unescaped.push(' '),
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some('\\') => unescaped.push('\\'),
+            Some(c) => {
+                unescaped.push('\\');
+                unescaped.push(c);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(exec: &str) -> Result<Vec<String>, String> {
+        expand_exec(
+            exec,
+            Some("gimp"),
+            "GIMP",
+            Some("/usr/share/applications/gimp.desktop"),
+        )
+    }
+
+    #[test]
+    fn file_and_url_codes() {
+        assert_eq!(expand("gimp-2.10 %U").unwrap(), ["gimp-2.10"]);
+        assert_eq!(
+            expand("evince %f --fullscreen %F").unwrap(),
+            ["evince", "--fullscreen"]
+        );
+        assert_eq!(
+            expand("steam steam://rungameid/570").unwrap(),
+            ["steam", "steam://rungameid/570"]
+        );
+    }
+
+    #[test]
+    fn quoting() {
+        // Exec=sh -c "echo \\"$1\\" \\$HOME" as written in the desktop file
+        let exec = unescape_string(r#"sh -c "echo \\"hi\\" \\$HOME""#);
+        assert_eq!(expand(&exec).unwrap(), ["sh", "-c", r#"echo "hi" $HOME"#]);
+        assert_eq!(
+            expand(r#""/opt/My App/run" --x"#).unwrap(),
+            ["/opt/My App/run", "--x"]
+        );
+        assert_eq!(expand("a 'b c' d\\ e").unwrap(), ["a", "b c", "d e"]);
+        // a quoted field code stays a (possibly empty) argument
+        assert_eq!(expand(r#"app "%u""#).unwrap(), ["app", ""]);
+    }
+
+    #[test]
+    fn percent_sign() {
+        assert_eq!(expand("printf 100%%").unwrap(), ["printf", "100%"]);
+        assert_eq!(expand(r#"date "+%%Y""#).unwrap(), ["date", "+%Y"]);
+    }
+
+    #[test]
+    fn icon_name_and_file() {
+        assert_eq!(expand("gimp %i").unwrap(), ["gimp", "--icon", "gimp"]);
+        assert_eq!(
+            expand_exec("gimp %i", None, "GIMP", None).unwrap(),
+            ["gimp"]
+        );
+        assert_eq!(
+            expand_exec("gimp %i", Some(""), "GIMP", None).unwrap(),
+            ["gimp"]
+        );
+        assert!(expand("gimp --icon=%i").is_err());
+        assert_eq!(
+            expand("app --title %c").unwrap(),
+            ["app", "--title", "GIMP"]
+        );
+        assert_eq!(expand("app --title=%c").unwrap(), ["app", "--title=GIMP"]);
+        assert_eq!(
+            expand("app %k").unwrap(),
+            ["app", "/usr/share/applications/gimp.desktop"]
+        );
+        assert_eq!(expand_exec("app %k", None, "App", None).unwrap(), ["app"]);
+    }
+
+    #[test]
+    fn deprecated_codes() {
+        assert_eq!(expand("xterm %d %D %n %N %v %m").unwrap(), ["xterm"]);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(expand("app 100%").is_err());
+        assert!(expand("app %x").is_err());
+        assert!(expand(r#"sh -c "echo"#).is_err());
+        assert!(expand("sh -c 'echo").is_err());
+        assert!(expand("").is_err());
+        assert!(expand("   ").is_err());
+        assert!(expand("%U").is_err());
+    }
+
+    #[test]
+    fn unescape() {
+        assert_eq!(unescape_string(r"a\sb\tc\\d\q"), "a b\tc\\d\\q");
+    }
+}
@@ -1,63 +1,391 @@
 use super::util::get_history_file;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs::File;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{File, OpenOptions};
 use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Copy, Clone, Default, Eq, Deserialize, Serialize)]
+// version 1 was a plain map from ids to HistoryData without frecency
+const HISTORY_VERSION: u32 = 2;
+// maximum number of remembered (query, id) pairs
+const QUERY_HISTORY_SIZE: usize = 1000;
+// only prefixes up to this number of characters are remembered for a query
+const QUERY_MAX_LENGTH: usize = 16;
+
+#[derive(Copy, Clone, Default, Deserialize, Serialize)]
 pub struct HistoryData {
     pub last_used: u64,
     pub usage_count: u32,
+    // number of launches decayed by their age at the time of last_used
+    #[serde(default)]
+    pub frecency: f64,
 }
 
 impl PartialEq for HistoryData {
     fn eq(&self, other: &Self) -> bool {
-        self.last_used.eq(&other.last_used) && self.usage_count.eq(&other.usage_count)
+        self.last_used.eq(&other.last_used)
+            && self.usage_count.eq(&other.usage_count)
+            && self.frecency.eq(&other.frecency)
+    }
+}
+
+impl Eq for HistoryData {}
+
+impl HistoryData {
+    // frecency at the given time, launches lose half their weight every half_life days
+    pub fn frecency_at(&self, time: u64, half_life: f64) -> f64 {
+        let age = time.saturating_sub(self.last_used) as f64 / 86400.0;
+        self.frecency * 0.5f64.powf(age / half_life)
     }
 }
 
-pub fn load_history(days: u32) -> HashMap<String, HistoryData> {
-    match get_history_file(false) {
-        Some(file) => {
-            let history_str = std::fs::read_to_string(file).expect("Cannot read history file");
-            let epoch = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards");
-            let cutoff = epoch.as_secs() - (days as u64) * 86400;
-            let mut history = toml::from_str(&history_str)
-                .unwrap_or_else(|err| {
-                    eprintln!("Cannot parse history file: {}", err);
-                    HashMap::new()
-                });
-            history.retain(|_, data : &mut HistoryData| {
-                    days == 0 || data.last_used >= cutoff
-            });
-            return history;
+#[derive(Copy, Clone, Default, Deserialize, Serialize)]
+pub struct QueryData {
+    pub last_used: u64,
+    pub count: u32,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct History {
+    // ids of entries the user pinned to the top, must come before the tables in the file
+    #[serde(default)]
+    pub pinned: BTreeSet<String>,
+    pub apps: HashMap<String, HistoryData>,
+    // how often an entry was launched after typing (a prefix of) a query, by query and id
+    #[serde(default)]
+    pub queries: HashMap<String, HashMap<String, QueryData>>,
+}
+
+impl History {
+    // the entries launched for the longest prefix of the query that was typed before
+    pub fn query_selections(&self, query: &str) -> Option<&HashMap<String, QueryData>> {
+        query_prefixes(query)
+            .iter()
+            .rev()
+            .find_map(|prefix| self.queries.get(prefix))
+    }
+
+    pub fn set_pinned(&mut self, id: &str, pinned: bool) {
+        if pinned {
+            self.pinned.insert(id.to_string());
+        } else {
+            self.pinned.remove(id);
+        }
+    }
+
+    // remove everything we know about an entry, returns whether there was anything
+    pub fn forget(&mut self, id: &str) -> bool {
+        let mut found = self.apps.remove(id).is_some();
+        for selections in self.queries.values_mut() {
+            found |= selections.remove(id).is_some();
         }
-        _ => HashMap::new(),
+        self.queries.retain(|_, selections| !selections.is_empty());
+        found
     }
+
+    // drop apps and queries that were not used in the given number of days (0 keeps everything)
+    fn prune(&mut self, days: u32) {
+        let cutoff = if days == 0 { 0 } else { now().saturating_sub(days as u64 * 86400) };
+        self.apps.retain(|_, data| data.last_used >= cutoff);
+        self.prune_queries(cutoff);
+    }
+
+    // drop selections older than cutoff and the oldest ones beyond QUERY_HISTORY_SIZE
+    fn prune_queries(&mut self, cutoff: u64) {
+        for selections in self.queries.values_mut() {
+            selections.retain(|_, data| data.last_used >= cutoff);
+        }
+
+        let mut pairs: Vec<(u64, Reverse<usize>, String, String)> = self
+            .queries
+            .iter()
+            .flat_map(|(query, selections)| {
+                selections.iter().map(move |(id, data)| {
+                    (
+                        data.last_used,
+                        Reverse(query.len()),
+                        query.clone(),
+                        id.clone(),
+                    )
+                })
+            })
+            .collect();
+        if pairs.len() > QUERY_HISTORY_SIZE {
+            // of pairs used at the same time, the longer queries go first since the shorter
+            // prefixes are typed more often
+            pairs.sort_unstable();
+            let excess = pairs.len() - QUERY_HISTORY_SIZE;
+            for (_, _, query, id) in pairs.drain(..excess) {
+                if let Some(selections) = self.queries.get_mut(&query) {
+                    selections.remove(&id);
+                }
+            }
+        }
+        self.queries.retain(|_, selections| !selections.is_empty());
+    }
+}
+
+// normalized prefixes of a query, shortest first
+fn query_prefixes(query: &str) -> Vec<String> {
+    let query = query.trim().to_lowercase();
+    query
+        .char_indices()
+        .take(QUERY_MAX_LENGTH)
+        .map(|(i, c)| query[..i + c.len_utf8()].to_string())
+        .collect()
+}
+
+#[derive(Deserialize, Serialize)]
+struct HistoryFile<T> {
+    version: u32,
+    #[serde(flatten)]
+    history: T,
 }
 
-pub fn save_history(history: &HashMap<String, HistoryData>) {
-    let file = get_history_file(true).expect("Cannot create history file or cache directory");
-    let mut file = File::create(file).expect("Cannot open history file for writing");
-    let s = toml::to_string(history).unwrap();
-    file.write_all(s.as_bytes())
-        .expect("Cannot write to history file");
+pub fn parse_history(history_str: &str) -> Result<History, toml::de::Error> {
+    match toml::from_str::<HistoryFile<History>>(history_str) {
+        Ok(file) => Ok(file.history),
+        Err(err) => {
+            // migrate from version 1, assuming that all launches happened at last_used
+            let mut apps: HashMap<String, HistoryData> =
+                toml::from_str(history_str).map_err(|_| err)?;
+            for data in apps.values_mut() {
+                data.frecency = data.usage_count as f64;
+            }
+            Ok(History {
+                apps,
+                ..Default::default()
+            })
+        }
+    }
 }
 
-pub fn update_history(history: &mut HashMap<String, HistoryData>, id: &str) {
-    let epoch = SystemTime::now()
+pub fn now() -> u64 {
+    SystemTime::now()
         .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards");
-    let usage_count = history.get(&id.to_string()).map_or(0, |h| h.usage_count) + 1;
+        .expect("Time went backwards")
+        .as_secs()
+}
 
-    history.insert(
+// the history file as written by the last successful save, used when the history file is corrupt
+fn backup_file(file: &Path) -> PathBuf {
+    file.with_extension("bak")
+}
+
+fn read_history_file(file: &Path) -> Result<History, String> {
+    let history_str = std::fs::read_to_string(file).map_err(|err| err.to_string())?;
+    parse_history(&history_str).map_err(|err| err.to_string())
+}
+
+fn read_history() -> History {
+    let file = match get_history_file(false) {
+        Some(file) => file,
+        _ => return History::default(),
+    };
+    read_history_file(&file).unwrap_or_else(|err| {
+        eprintln!("Cannot read history file {}: {}", file.display(), err);
+        match read_history_file(&backup_file(&file)) {
+            Ok(history) => {
+                eprintln!("Using backup of history file");
+                history
+            }
+            Err(_) => History::default(),
+        }
+    })
+}
+
+pub fn load_history(days: u32) -> History {
+    let mut history = read_history();
+    history.prune(days);
+    history
+}
+
+pub fn history_to_string(history: &History) -> Result<String, String> {
+    toml::to_string(&HistoryFile {
+        version: HISTORY_VERSION,
+        history,
+    })
+    .map_err(|err| err.to_string())
+}
+
+// write the history to a temporary file first, so the history file is replaced atomically
+pub fn save_history(history: &History) -> Result<(), String> {
+    let file = get_history_file(true).ok_or("Cannot create history file or cache directory")?;
+    let s = history_to_string(history)?;
+
+    let tmp_file = file.with_extension("tmp");
+    let mut tmp = File::create(&tmp_file).map_err(|err| err.to_string())?;
+    tmp.write_all(s.as_bytes())
+        .and_then(|_| tmp.sync_all())
+        .map_err(|err| err.to_string())?;
+
+    // keep the current file if it is intact, the new one is only known to be good after the rename
+    if read_history_file(&file).is_ok() {
+        if let Err(err) = std::fs::copy(&file, backup_file(&file)) {
+            eprintln!("Cannot back up history file: {}", err);
+        }
+    }
+    std::fs::rename(&tmp_file, &file).map_err(|err| err.to_string())
+}
+
+// advisory lock on the history, released when dropped
+struct HistoryLock(File);
+
+impl HistoryLock {
+    fn acquire() -> Result<HistoryLock, String> {
+        let file = get_history_file(true)
+            .ok_or("Cannot create history file or cache directory")?
+            .with_extension("lock");
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(file)
+            .map_err(|err| err.to_string())?;
+        if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+        Ok(HistoryLock(lock))
+    }
+}
+
+impl Drop for HistoryLock {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.0.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+// Apply change to the history on disk and save it while holding the lock, so that changes of
+// other sirula instances since we loaded the history are kept. history is replaced by the result.
+pub fn modify_history(history: &mut History, days: u32, change: impl FnOnce(&mut History)) {
+    let _lock = match HistoryLock::acquire() {
+        Ok(lock) => lock,
+        Err(err) => {
+            // without the lock we might overwrite a save of another instance, so only change
+            // the history in memory
+            eprintln!("Cannot lock history file, not saving history: {}", err);
+            change(history);
+            return;
+        }
+    };
+    let mut current = read_history();
+    current.prune(days);
+    change(&mut current);
+    if let Err(err) = save_history(&current) {
+        eprintln!("Cannot save history: {}", err);
+    }
+    *history = current;
+}
+
+pub fn update_history(history: &mut History, id: &str, query: &str, half_life: f64) {
+    let now = now();
+    let data = history.apps.get(&id.to_string()).copied().unwrap_or_default();
+
+    for prefix in query_prefixes(query) {
+        let selections = history.queries.entry(prefix).or_default();
+        let count = selections.get(id).map_or(0, |q| q.count) + 1;
+        selections.insert(id.to_string(), QueryData { last_used: now, count });
+    }
+    history.prune_queries(0);
+
+    history.apps.insert(
         id.to_string(),
         HistoryData {
-            last_used: epoch.as_secs(),
-            usage_count,
+            last_used: now,
+            usage_count: data.usage_count + 1,
+            frecency: data.frecency_at(now, half_life) + 1.0,
         },
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // the tests change XDG_CACHE_HOME, which is shared by the whole process
+    static CACHE_HOME: Mutex<()> = Mutex::new(());
+
+    fn with_cache_home(name: &str, f: impl FnOnce()) {
+        let _guard = CACHE_HOME.lock().unwrap_or_else(|err| err.into_inner());
+        let dir = std::env::temp_dir().join(format!("sirula-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::env::set_var("XDG_CACHE_HOME", &dir);
+        f();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn concurrent_writers() {
+        with_cache_home("concurrent", || {
+            let threads: Vec<_> = (0..8)
+                .map(|t| {
+                    std::thread::spawn(move || {
+                        // every thread starts from an empty history, like an instance that
+                        // loaded the history before the others saved theirs
+                        let mut history = History::default();
+                        for i in 0..10 {
+                            modify_history(&mut history, 0, |h| {
+                                update_history(h, "shared", "", 7.0);
+                                update_history(h, &format!("app{}-{}", t, i), "q", 7.0);
+                            });
+                        }
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+
+            let history = read_history();
+            assert_eq!(history.apps["shared"].usage_count, 80);
+            assert_eq!(history.apps.len(), 81);
+            assert_eq!(history.queries["q"].len(), 80);
+        });
+    }
+
+    #[test]
+    fn prune_exactly_the_oldest_queries() {
+        let mut history = History::default();
+        // the ten oldest pairs were used in the same second
+        for i in 0..QUERY_HISTORY_SIZE {
+            let selections = history.queries.entry(format!("query{}", i)).or_default();
+            selections.insert(
+                "app".to_string(),
+                QueryData {
+                    last_used: 100 + i.saturating_sub(9) as u64,
+                    count: 1,
+                },
+            );
+        }
+        // one launch remembers all prefixes of the query at the same time
+        update_history(&mut history, "new", "firefox", 7.0);
+
+        let count: usize = history.queries.values().map(|s| s.len()).sum();
+        assert_eq!(count, QUERY_HISTORY_SIZE);
+        for i in 0..7 {
+            assert!(!history.queries.contains_key(&format!("query{}", i)));
+        }
+        assert!(history.queries.contains_key("query7"));
+        assert!(history.queries.contains_key("firefox"));
+    }
+
+    #[test]
+    fn corrupt_history_uses_backup() {
+        with_cache_home("backup", || {
+            let mut history = History::default();
+            modify_history(&mut history, 0, |h| update_history(h, "first", "", 7.0));
+            modify_history(&mut history, 0, |h| update_history(h, "second", "", 7.0));
+
+            // the backup holds the history before the last save
+            let file = get_history_file(false).unwrap();
+            std::fs::write(&file, "version = [").unwrap();
+            let history = read_history();
+            assert!(history.apps.contains_key("first"));
+            assert!(!history.apps.contains_key("second"));
+        });
+    }
+}
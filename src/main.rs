@@ -15,17 +15,18 @@ You should have received a copy of the GNU General Public License
 along with sirula.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use fuzzy_matcher::skim::SkimMatcherV2;
 use gdk::keys::constants;
 use gio::prelude::*;
 use gtk::{
-    builders::{BoxBuilder, EntryBuilder, ListBoxBuilder, ScrolledWindowBuilder},
+    builders::{BoxBuilder, EntryBuilder},
     prelude::*,
-    ListBoxRow,
 };
 use libc::LC_ALL;
-use std::env::args;
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::io::{BufRead, Write};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
 
 mod consts;
 use consts::*;
@@ -36,19 +37,81 @@ use config::*;
 mod util;
 use util::*;
 
+mod exec;
+
 mod app_entry;
 use app_entry::*;
 
+mod entry_cache;
+
+mod entry_list;
+use entry_list::*;
+
 mod locale;
 use locale::*;
 
 mod history;
 use history::*;
 
-fn app_startup(application: &gtk::Application) {
-    let config = Config::load();
+mod cli;
+use cli::*;
+
+mod matcher;
+use matcher::*;
+
+// in daemon mode, the window is only hidden so that it can be shown again quickly
+fn close_window(window: &gtk::ApplicationWindow, daemon: bool) {
+    if daemon {
+        window.hide();
+    } else {
+        window.close();
+    }
+}
+
+// lines of stdin as bytes, they need not be UTF-8 (e.g., Latin-1 file names)
+fn read_lines() -> Vec<Vec<u8>> {
+    let mut stdin = std::io::stdin().lock();
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match stdin.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {
+                if line.ends_with(b"\n") {
+                    line.pop();
+                    if line.ends_with(b"\r") {
+                        line.pop();
+                    }
+                }
+                lines.push(line.clone());
+            }
+            Err(err) => {
+                eprintln!("Cannot read from stdin: {}", err);
+                break;
+            }
+        }
+    }
+    lines
+}
+
+fn app_startup(
+    application: &gtk::Application,
+    daemon: bool,
+    rebuild_cache: bool,
+    timing: bool,
+    dmenu_lines: Option<&[Vec<u8>]>,
+    exit_status: Rc<Cell<i32>>,
+) {
+    let config = Rc::new(Config::load());
     let launch_cgroups = config.cgroups;
-    let cmd_prefix = config.command_prefix.clone();
+    let dmenu = dmenu_lines.is_some();
+    // commands make no sense when picking from stdin
+    let cmd_prefix = if dmenu {
+        String::new()
+    } else {
+        config.command_prefix.clone()
+    };
 
     let window = gtk::ApplicationWindow::new(application);
     window.set_size_request(config.width, config.height);
@@ -82,142 +145,363 @@ fn app_startup(application: &gtk::Application) {
     let entry = EntryBuilder::new().name(SEARCH_ENTRY_NAME).build(); // .width_request(300)
     vbox.pack_start(&entry, false, false, 0);
 
-    let scroll = ScrolledWindowBuilder::new()
-        .name(SCROLL_NAME)
-        .hscrollbar_policy(gtk::PolicyType::Never)
-        .build();
-    vbox.pack_end(&scroll, true, true, 0);
-
-    let listbox = ListBoxBuilder::new().name(LISTBOX_NAME).build();
-    scroll.add(&listbox);
-
     let history = Rc::new(RefCell::new(load_history(config.prune_history)));
-    let entries = Rc::new(RefCell::new(load_entries(&config, &history.borrow())));
-
-    for row in (&entries.borrow() as &HashMap<ListBoxRow, AppEntry>).keys() {
-        listbox.add(row);
-    }
-
-    window.connect_key_press_event(clone!(entry, listbox, entries => move |window, event| {
-        use constants::*;
-        #[allow(non_upper_case_globals)]
-        Inhibit(match event.keyval() {
-            Escape => {
-                window.close();
-                true
-            },
-            Down | KP_Down | Tab if entry.has_focus() => {
-                if let Some(r0) = listbox.row_at_index(0) {
-                    let es = entries.borrow();
-                    if r0.is_selected() {
-                        if let Some(r1) = listbox.row_at_index(1) {
-                            if let Some(app_entry) = es.get(&r1) {
-                                if !app_entry.hidden() {
-                                    listbox.select_row(Some(&r1));
-                                }
-                            }
-                        }
-                    } else if let Some(app_entry) = es.get(&r0) {
-                        if !app_entry.hidden() {
-                            listbox.select_row(Some(&r0));
-                        }
-                    }
-                }
-                false
-            },
-            Up | Down | KP_Up | KP_Down | Page_Up | Page_Down | KP_Page_Up | KP_Page_Down | Tab
-            | Shift_L | Shift_R | Control_L | Control_R | Alt_L | Alt_R | ISO_Left_Tab | Return
-            | KP_Enter => false,
-            _ => {
-                if !event.is_modifier() && !entry.has_focus() {
-                    entry.grab_focus_without_selecting();
-                }
-                false
-            }
-        })
+    let entries = Rc::new(RefCell::new(match dmenu_lines {
+        Some(lines) => load_dmenu_entries(lines, &config),
+        None => load_entries(&config, &history.borrow(), rebuild_cache, timing),
     }));
 
+    // there are no icons to show for lines from stdin
+    let list = EntryList::new(entries.clone(), config.clone(), !dmenu);
+    vbox.pack_end(list.widget(), true, true, 0);
+
 	if config.close_on_unfocus {
-	    window.connect_focus_out_event(|window, _| {
-    	    window.close();
+	    window.connect_focus_out_event(move |window, _| {
+    	    close_window(window, daemon);
     	    Inhibit(false)
     	});
     }
 
-    let matcher = SkimMatcherV2::default();
+    let matcher = Rc::new(Matcher::new(config.match_mode));
     let term_command = config.term_command.clone();
-    entry.connect_changed(clone!(entries, listbox, cmd_prefix => move |e| {
-        let text = e.text();
+    // query and match mode of the last completed search, if the next one may only consider its matches
+    let last_search: Rc<RefCell<Option<(String, MatchMode)>>> = Rc::new(RefCell::new(None));
+    // incremented for every search, so that unfinished searches for old queries stop
+    let search_generation = Rc::new(Cell::new(0u64));
+    // history id of the entry to select again after the next search, when the apps changed
+    let reselect: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(None));
+    entry.connect_changed(clone!(entries, list, cmd_prefix, config, matcher, history, last_search, search_generation, reselect => move |e| {
+        let text = e.text().to_string();
         let is_cmd = is_cmd(&text, &cmd_prefix);
-        {
-            let mut entries = entries.borrow_mut();
-            for entry in entries.values_mut() {
-                if is_cmd {
-                    entry.hide(); // hide entries in command mode
-                } else {
-                    entry.update_match(&text, &matcher, &config);
+        let generation = search_generation.get() + 1;
+        search_generation.set(generation);
+        let reselect = reselect.borrow_mut().take();
+
+        // entries that do not match a query cannot match its extensions
+        let narrow = matches!(last_search.borrow_mut().take(), Some((last, mode))
+            if !last.is_empty() && text.starts_with(&last) && mode == matcher.mode());
+        let mut candidates = entries
+            .borrow()
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| !narrow || !e.hidden())
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>()
+            .into_iter();
+
+        // match in chunks, so that typing is not blocked by long lists
+        let mut search = clone!(entries, list, config, matcher, history, last_search, search_generation => move || {
+            if search_generation.get() != generation {
+                return glib::Continue(false);
+            }
+            let mut typos = false;
+            {
+                let history = history.borrow();
+                let selections = history.query_selections(&text);
+                let mut entries = entries.borrow_mut();
+                for i in candidates.by_ref().take(MATCH_CHUNK_SIZE) {
+                    if let Some(entry) = entries.get_mut(i) {
+                        if is_cmd {
+                            entry.hide(); // hide entries in command mode
+                        } else {
+                            entry.update_match(&text, &matcher, &config, selections);
+                        }
+                    }
+                }
+                if !candidates.as_slice().is_empty() {
+                    return glib::Continue(true);
+                }
+
+                // in dmenu mode, a query that matches nothing is printed as typed instead
+                let nothing_matched = entries.iter().all(|e| e.hidden());
+                if nothing_matched && !is_cmd && !dmenu && !text.is_empty() && config.max_typos > 0 {
+                    typos = true;
+                    for entry in entries.iter_mut() {
+                        entry.update_typo_match(&text, &config);
+                    }
                 }
             }
+            if !is_cmd && !typos && matcher.mode() != MatchMode::Regex {
+                *last_search.borrow_mut() = Some((text.clone(), matcher.mode()));
+            }
+            list.update();
+            // the entries moved, so find the selected one by its id
+            let selected = reselect.as_ref().and_then(|id| {
+                entries.borrow().iter().position(|e| e.kind.history_id().as_ref() == Some(id))
+            });
+            if let Some(i) = selected {
+                list.select_entry(i);
+            }
+            glib::Continue(false)
+        });
+        if search().0 {
+            glib::idle_add_local(search);
         }
-        listbox.invalidate_filter();
-        listbox.invalidate_sort();
-        listbox.select_row(listbox.row_at_index(0).as_ref());
     }));
 
-    entry.connect_activate(clone!(listbox, window => move |e| {
+    let launch_entry = {
+        let (entries, entry, window, history, exit_status, config) = (
+            entries.clone(),
+            entry.clone(),
+            window.clone(),
+            history.clone(),
+            exit_status.clone(),
+            config.clone(),
+        );
+        Rc::new(move |i: usize, in_terminal: bool| {
+            let mut es = entries.borrow_mut();
+            if let Some(e) = es.get_mut(i).filter(|e| !e.hidden()) {
+                let term_command = term_command.as_deref();
+                match &e.kind {
+                    EntryKind::App(app) => {
+                        if let Some(info) = app.info() {
+                            let overrides = config.app_override(&info);
+                            launch_app(&info, None, overrides, term_command, in_terminal, launch_cgroups)
+                        }
+                    }
+                    EntryKind::Action(app, action) => {
+                        if let Some(info) = app.info() {
+                            launch_app(
+                                &info,
+                                Some(action),
+                                config.app_override(&info),
+                                term_command,
+                                in_terminal,
+                                launch_cgroups,
+                            )
+                        }
+                    }
+                    EntryKind::Custom(custom) => {
+                        launch_custom(custom, term_command, in_terminal, launch_cgroups)
+                    }
+                    EntryKind::Dmenu(_, line) => {
+                        // print the line as it was read, so that file names stay valid
+                        let mut stdout = std::io::stdout();
+                        if let Err(err) = stdout.write_all(line).and_then(|_| stdout.write_all(b"\n")) {
+                            eprintln!("Cannot write to stdout: {}", err);
+                        }
+                        exit_status.set(EXIT_SELECTED);
+                    }
+                }
+
+                if let Some(id) = e.kind.history_id() {
+                    let mut history = history.borrow_mut();
+                    let query = entry.text();
+                    modify_history(&mut history, config.prune_history, |h| {
+                        update_history(h, &id, &query, config.frecency_half_life)
+                    });
+                    e.set_history(&history, &id, &config);
+                }
+
+                close_window(&window, daemon);
+            }
+        })
+    };
+
+    list.connect_activated(clone!(launch_entry => move |i| launch_entry(i, false)));
+
+    entry.connect_activate(clone!(list, window, exit_status, cmd_prefix, launch_entry => move |e| {
         let text = e.text();
         if is_cmd(&text, &cmd_prefix) { // command execution direct
             let cmd_line = &text[cmd_prefix.len()..].trim();
             launch_cmd(cmd_line);
-            window.close();
-        } else if let Some(row) = listbox.row_at_index(0) {
-            row.activate();
+            close_window(&window, daemon);
+        } else if let Some(i) = list.selected_entry() {
+            launch_entry(i, false);
+        } else if dmenu && !text.is_empty() { // nothing matches, print what was typed
+            println!("{}", text);
+            exit_status.set(EXIT_CUSTOM);
+            close_window(&window, daemon);
         }
     }));
 
-    listbox.connect_row_activated(clone!(entries, window, history => move |_, r| {
-        let es = entries.borrow();
-        let e = &es[r];
-        if !e.hidden() {
-            launch_app(&e.info, term_command.as_deref(), launch_cgroups);
-
-            let mut history = history.borrow_mut();
-            update_history(&mut history, e.info.id().unwrap().as_str());
-            save_history(&history);
-
-            window.close();
+    window.connect_key_press_event(clone!(entry, list, entries, history, config, matcher, launch_entry => move |window, event| {
+        let key = (
+            *event.keyval().to_lower(),
+            event.state() & gtk::accelerator_get_default_mod_mask(),
+        );
+        if let Some(action) = config.keybindings.get(&key) {
+            match action {
+                KeyAction::SelectNext => list.move_selection(1),
+                KeyAction::SelectPrev => list.move_selection(-1),
+                KeyAction::PageDown => list.move_selection(list.page_size()),
+                KeyAction::PageUp => list.move_selection(-list.page_size()),
+                KeyAction::Launch | KeyAction::LaunchInTerminal => {
+                    if let Some(i) = list.selected_entry() {
+                        launch_entry(i, *action == KeyAction::LaunchInTerminal);
+                    }
+                }
+                KeyAction::Close => close_window(window, daemon),
+                KeyAction::ClearQuery => {
+                    entry.set_text("");
+                    entry.grab_focus_without_selecting();
+                }
+                KeyAction::DeleteHistoryEntry => {
+                    if let Some(i) = list.selected_entry() {
+                        {
+                            let mut es = entries.borrow_mut();
+                            let id = es.get(i).and_then(|e| e.kind.history_id());
+                            if let (Some(e), Some(id)) = (es.get_mut(i), id) {
+                                let mut history = history.borrow_mut();
+                                modify_history(&mut history, config.prune_history, |h| {
+                                    h.forget(&id);
+                                });
+                                e.selections = 0;
+                                e.set_history(&history, &id, &config);
+                            }
+                        }
+                        list.resort();
+                    }
+                }
+                KeyAction::CycleMatchMode => {
+                    let mode = matcher.mode().next();
+                    matcher.set_mode(mode);
+                    entry.set_placeholder_text(Some(mode.label()));
+                    entry.emit_by_name::<()>("changed", &[]);
+                }
+                KeyAction::TogglePin => {
+                    if let Some(i) = list.selected_entry() {
+                        {
+                            let mut es = entries.borrow_mut();
+                            let id = es.get(i).and_then(|e| e.kind.history_id());
+                            if let (Some(e), Some(id)) = (es.get_mut(i), id) {
+                                let pinned = !e.pinned;
+                                let mut history = history.borrow_mut();
+                                modify_history(&mut history, config.prune_history, |h| {
+                                    h.set_pinned(&id, pinned)
+                                });
+                                e.set_pinned(pinned, &config);
+                            }
+                        }
+                        list.resort();
+                    }
+                }
+            }
+            return Inhibit(true);
         }
-    }));
-
-    listbox.set_filter_func(Some(Box::new(clone!(entries => move |r| {
-        let e = entries.borrow();
-        !e[r].hidden()
-    }))));
 
-    listbox.set_sort_func(Some(Box::new(clone!(entries => move |a, b| {
-        let e = entries.borrow();
-        e[a].cmp(&e[b]) as i32
-    }))));
+        // the rows cannot be focused, the list is navigated from the search entry
+        use constants::*;
+        #[allow(non_upper_case_globals)]
+        Inhibit(match event.keyval() {
+            Down | KP_Down | Tab => {
+                list.move_selection(1);
+                true
+            }
+            Up | KP_Up | ISO_Left_Tab => {
+                list.move_selection(-1);
+                true
+            }
+            Page_Down | KP_Page_Down => {
+                list.move_selection(list.page_size());
+                true
+            }
+            Page_Up | KP_Page_Up => {
+                list.move_selection(-list.page_size());
+                true
+            }
+            Shift_L | Shift_R | Control_L | Control_R | Alt_L | Alt_R | Return | KP_Enter => false,
+            _ => {
+                if !event.is_modifier() && !entry.has_focus() {
+                    entry.grab_focus_without_selecting();
+                }
+                false
+            }
+        })
+    }));
 
-    listbox.select_row(listbox.row_at_index(0).as_ref());
+    list.update();
+
+    if !dmenu {
+        let refresh = Rc::new(clone!(entries, entry, list, history, config, reselect => move || {
+            let selected = list
+                .selected_entry()
+                .and_then(|i| entries.borrow().get(i).and_then(|e| e.kind.history_id()));
+            let changed = refresh_entries(&mut entries.borrow_mut(), &config, &history.borrow());
+            if changed {
+                // the list still shows the entries by their old indices
+                list.update();
+                *reselect.borrow_mut() = selected;
+                // match again, this also stops a search that still uses the old entries
+                entry.emit_by_name::<()>("changed", &[]);
+            }
+        }));
+
+        let monitor = gio::AppInfoMonitor::get();
+        // the handler holds a reference to the monitor to keep it alive
+        monitor.connect_changed(clone!(monitor, refresh => move |_| {
+            let _ = &monitor;
+            refresh();
+        }));
+
+        // GIO only reports changes after the apps were listed once, which did not happen if the
+        // entries came from the cache. Listing them also picks up apps installed since then.
+        glib::idle_add_local(move || {
+            refresh();
+            glib::Continue(false)
+        });
+    }
 
     window.add(&vbox);
-    window.show_all()
+
+    if daemon {
+        // keep running without a visible window, a second invocation of sirula activates us
+        application.hold();
+
+        application.connect_activate(clone!(window, entry, list => move |_| {
+            entry.set_text("");
+            list.update();
+            window.show_all();
+            entry.grab_focus();
+        }));
+    } else {
+        window.show_all()
+    }
 }
 
 fn main() {
     set_locale(LC_ALL, "");
 
-    let application = gtk::Application::new(Some(APP_ID), Default::default());
+    let args = Args::parse();
+
+    if let Some(command) = args.history {
+        std::process::exit(run_history_command(command));
+    }
 
-    application.connect_startup(|app| {
+    let dmenu_lines: Option<Vec<Vec<u8>>> = if args.dmenu {
+        Some(read_lines())
+    } else {
+        None
+    };
+    let exit_status = Rc::new(Cell::new(if args.dmenu { EXIT_CANCELLED } else { 0 }));
+
+    // pickers must not be forwarded to an already running launcher
+    let flags = if args.dmenu {
+        gio::ApplicationFlags::NON_UNIQUE
+    } else if args.daemon {
+        gio::ApplicationFlags::IS_SERVICE
+    } else {
+        Default::default()
+    };
+    let application = gtk::Application::new(Some(APP_ID), flags);
+
+    let (daemon, rebuild_cache, timing) = (args.daemon, args.rebuild_cache, args.timing);
+    application.connect_startup(clone!(exit_status => move |app| {
         load_css();
-        app_startup(app);
-    });
+        app_startup(
+            app,
+            daemon,
+            rebuild_cache,
+            timing,
+            dmenu_lines.as_deref(),
+            exit_status.clone(),
+        );
+    }));
 
     application.connect_activate(|_| {
         //do nothing
     });
 
-    application.run_with_args(&args().collect::<Vec<_>>());
+    application.run_with_args(&args.gtk_args);
+    std::process::exit(exit_status.get());
 }
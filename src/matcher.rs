@@ -0,0 +1,446 @@
+/*
+Copyright (C) 2020 Dorian Rudolph
+
+sirula is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+sirula is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with sirula.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use caseless::Caseless;
+use fuzzy_matcher::{clangd::ClangdMatcher, skim::SkimMatcherV2, FuzzyMatcher};
+use regex::{Regex, RegexBuilder};
+use serde_derive::Deserialize;
+use std::cell::{Cell, RefCell};
+use std::iter::once;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    Fuzzy,
+    ClangdFuzzy,
+    // every word of the query is the beginning of a word
+    WordPrefix,
+    Substring,
+    Regex,
+    // every word of the query is contained somewhere
+    MultiWord,
+}
+
+const MATCH_MODES: [MatchMode; 6] = [
+    MatchMode::Fuzzy,
+    MatchMode::ClangdFuzzy,
+    MatchMode::WordPrefix,
+    MatchMode::Substring,
+    MatchMode::Regex,
+    MatchMode::MultiWord,
+];
+
+impl MatchMode {
+    pub fn next(self) -> MatchMode {
+        let i = MATCH_MODES.iter().position(|m| *m == self).unwrap();
+        MATCH_MODES[(i + 1) % MATCH_MODES.len()]
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MatchMode::Fuzzy => "fuzzy",
+            MatchMode::ClangdFuzzy => "clangd fuzzy",
+            MatchMode::WordPrefix => "word prefix",
+            MatchMode::Substring => "substring",
+            MatchMode::Regex => "regex",
+            MatchMode::MultiWord => "all words",
+        }
+    }
+}
+
+// score of an exact match at the start, matches further to the right score less
+const EXACT_SCORE: i64 = 200;
+
+pub struct Matcher {
+    mode: Cell<MatchMode>,
+    skim: SkimMatcherV2,
+    clangd: ClangdMatcher,
+    // the regex of the last pattern, None if it is invalid
+    regex: RefCell<Option<(String, Option<Regex>)>>,
+}
+
+impl Matcher {
+    pub fn new(mode: MatchMode) -> Matcher {
+        Matcher {
+            mode: Cell::new(mode),
+            skim: SkimMatcherV2::default(),
+            clangd: ClangdMatcher::default(),
+            regex: RefCell::new(None),
+        }
+    }
+
+    pub fn mode(&self) -> MatchMode {
+        self.mode.get()
+    }
+
+    pub fn set_mode(&self, mode: MatchMode) {
+        self.mode.set(mode);
+    }
+
+    // Score and char indices of the matched characters, None if choice does not match.
+    // Both strings are folded, so "zurich" matches "Zürich" and the indices refer to the chars of choice.
+    pub fn fuzzy_indices(&self, choice: &str, pattern: &str) -> Option<(i64, Vec<usize>)> {
+        let (folded, origins) = fold(choice, true);
+        let (score, indices) = self.match_folded(&folded, pattern)?;
+        let chars: Vec<char> = choice.chars().collect();
+        let mut indices: Vec<usize> = indices
+            .into_iter()
+            .flat_map(|i| {
+                // the combining marks we dropped belong to the matched char
+                let i = origins[i];
+                let marks = chars[i + 1..].iter().take_while(|c| is_combining_mark(**c)).count();
+                i..=i + marks
+            })
+            .collect();
+        indices.dedup();
+        Some((score, indices))
+    }
+
+    pub fn fuzzy_match(&self, choice: &str, pattern: &str) -> Option<i64> {
+        let (choice, _) = fold(choice, true);
+        match self.mode() {
+            MatchMode::Fuzzy => self.skim.fuzzy_match(&choice, &fold(pattern, true).0),
+            MatchMode::ClangdFuzzy => self.clangd.fuzzy_match(&choice, &fold(pattern, true).0),
+            _ => self.match_folded(&choice, pattern).map(|(score, _)| score),
+        }
+    }
+
+    fn match_folded(&self, choice: &str, pattern: &str) -> Option<(i64, Vec<usize>)> {
+        // case folding would change the meaning of escapes like \D, the regex ignores case anyway
+        if self.mode() == MatchMode::Regex {
+            return self.match_regex(choice, &fold(pattern, false).0);
+        }
+        let pattern = fold(pattern, true).0;
+        match self.mode() {
+            MatchMode::Fuzzy => self.skim.fuzzy_indices(choice, &pattern),
+            MatchMode::ClangdFuzzy => self.clangd.fuzzy_indices(choice, &pattern),
+            MatchMode::WordPrefix => match_words(choice, &pattern, true),
+            MatchMode::Substring => match_substring(choice, &pattern),
+            MatchMode::Regex => unreachable!(),
+            MatchMode::MultiWord => match_words(choice, &pattern, false),
+        }
+    }
+
+    // Char indices of the initials of the words of choice that make up the pattern (e.g., "lo" for
+    // "LibreOffice"), None if the pattern is not an acronym of choice. Only used for fuzzy matching.
+    pub fn match_initials(&self, choice: &str, pattern: &str) -> Option<Vec<usize>> {
+        if !matches!(self.mode(), MatchMode::Fuzzy | MatchMode::ClangdFuzzy) {
+            return None;
+        }
+        let pattern: Vec<char> = fold(pattern, true)
+            .0
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        // a single letter is not an acronym
+        if pattern.len() < 2 {
+            return None;
+        }
+
+        let chars: Vec<char> = choice.chars().collect();
+        let mut indices = Vec::new();
+        for (i, c) in chars.iter().enumerate() {
+            if indices.len() == pattern.len() {
+                break;
+            }
+            let word_start = i == 0
+                || !chars[i - 1].is_alphanumeric()
+                || (chars[i - 1].is_lowercase() && c.is_uppercase());
+            if word_start
+                && c.is_alphanumeric()
+                && fold(&c.to_string(), true).0.starts_with(pattern[indices.len()])
+            {
+                indices.push(i);
+            }
+        }
+        if indices.len() == pattern.len() {
+            Some(indices)
+        } else {
+            None
+        }
+    }
+
+    fn match_regex(&self, choice: &str, pattern: &str) -> Option<(i64, Vec<usize>)> {
+        let mut cache = self.regex.borrow_mut();
+        if !matches!(cache.as_ref(), Some((p, _)) if p == pattern) {
+            let regex = RegexBuilder::new(pattern).case_insensitive(true).build().ok();
+            *cache = Some((pattern.to_string(), regex));
+        }
+        let regex = cache.as_ref().and_then(|(_, r)| r.as_ref())?;
+        let m = regex.find(choice)?;
+        let start = choice[..m.start()].chars().count();
+        let len = m.as_str().chars().count();
+        Some((position_score(start), (start..start + len).collect()))
+    }
+}
+
+// Decompose s, drop combining marks and apply full case folding (e.g., "Å" and "ß" become "a"
+// and "ss"). Also returns the index of the char of s that each char of the result stems from.
+fn fold(s: &str, case_fold: bool) -> (String, Vec<usize>) {
+    let mut folded = String::with_capacity(s.len());
+    let mut origins = Vec::with_capacity(s.len());
+    for (i, c) in s.chars().enumerate() {
+        for c in once(c).nfd().filter(|c| !is_combining_mark(*c)) {
+            if case_fold {
+                for c in once(c).default_case_fold() {
+                    folded.push(c);
+                    origins.push(i);
+                }
+            } else {
+                folded.push(c);
+                origins.push(i);
+            }
+        }
+    }
+    (folded, origins)
+}
+
+fn position_score(start: usize) -> i64 {
+    EXACT_SCORE - (start as i64).min(EXACT_SCORE / 2)
+}
+
+fn find_chars(haystack: &[char], needle: &[char], word_start: bool) -> Option<usize> {
+    if needle.len() > haystack.len() {
+        return None;
+    }
+    (0..=haystack.len() - needle.len()).find(|&i| {
+        (!word_start || i == 0 || !haystack[i - 1].is_alphanumeric())
+            && haystack[i..i + needle.len()] == *needle
+    })
+}
+
+fn match_substring(choice: &str, pattern: &str) -> Option<(i64, Vec<usize>)> {
+    let needle: Vec<char> = pattern.chars().collect();
+    let haystack: Vec<char> = choice.chars().collect();
+    let start = find_chars(&haystack, &needle, false)?;
+    Some((position_score(start), (start..start + needle.len()).collect()))
+}
+
+// every whitespace separated term of the pattern has to match
+fn match_words(choice: &str, pattern: &str, word_start: bool) -> Option<(i64, Vec<usize>)> {
+    let haystack: Vec<char> = choice.chars().collect();
+    let mut score = 0;
+    let mut indices = Vec::new();
+    for term in pattern.split_whitespace() {
+        let needle: Vec<char> = term.chars().collect();
+        let start = find_chars(&haystack, &needle, word_start)?;
+        score += position_score(start);
+        indices.extend(start..start + needle.len());
+    }
+    indices.sort_unstable();
+    indices.dedup();
+    Some((score, indices))
+}
+
+// optimal string alignment distance, i.e., Damerau–Levenshtein without editing a substring twice
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+// Match every word of the pattern against a word of choice (or its beginning) with at most one
+// typo per four characters and at most max_typos per word. Returns the score, which is lower the
+// more typos there are, and the char indices of the matched words.
+pub fn typo_match(choice: &str, pattern: &str, max_typos: usize) -> Option<(i64, Vec<usize>)> {
+    let (folded, origins) = fold(choice, true);
+    let folded: Vec<char> = folded.chars().collect();
+    // words as ranges of folded chars
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in folded.iter().chain(once(&' ')).enumerate() {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                words.push(s..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    let mut score = 0;
+    let mut indices = Vec::new();
+    for term in fold(pattern, true).0.split_whitespace() {
+        let term: Vec<char> = term.chars().collect();
+        let allowed = max_typos.min(term.len() / 4);
+        let (distance, word) = words
+            .iter()
+            .map(|word| {
+                // highlight the whole word or only its beginning, whichever matched
+                let prefix = word.start..word.end.min(word.start + term.len());
+                let whole = edit_distance(&term, &folded[word.clone()]);
+                let beginning = edit_distance(&term, &folded[prefix.clone()]);
+                if whole <= beginning {
+                    (whole, word.clone())
+                } else {
+                    (beginning, prefix)
+                }
+            })
+            .min_by_key(|(distance, _)| *distance)?;
+        if distance > allowed {
+            return None;
+        }
+        score += (max_typos + 1 - distance) as i64;
+        indices.extend(word.map(|i| origins[i]));
+    }
+    indices.sort_unstable();
+    indices.dedup();
+    if indices.is_empty() {
+        None
+    } else {
+        Some((score, indices))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn highlights(choice: &str, pattern: &str) -> Option<Vec<usize>> {
+        let matcher = Matcher::new(MatchMode::Fuzzy);
+        matcher.fuzzy_indices(choice, pattern).map(|(_, indices)| indices)
+    }
+
+    #[test]
+    fn diacritics() {
+        assert_eq!(highlights("Zürich Transit", "zurich"), Some(vec![0, 1, 2, 3, 4, 5]));
+        assert_eq!(highlights("Zürich Transit", "zürich"), Some(vec![0, 1, 2, 3, 4, 5]));
+        assert_eq!(highlights("Édition", "edition"), Some(vec![0, 1, 2, 3, 4, 5, 6]));
+    }
+
+    #[test]
+    fn decomposed() {
+        // the combining diaeresis is highlighted with the u
+        assert_eq!(highlights("Zu\u{308}rich", "zurich"), Some(vec![0, 1, 2, 3, 4, 5, 6]));
+        assert_eq!(highlights("Zu\u{308}rich", "zürich"), Some(vec![0, 1, 2, 3, 4, 5, 6]));
+    }
+
+    #[test]
+    fn case_folding() {
+        // chars that fold to several chars are highlighted once
+        assert_eq!(highlights("ﬁle", "fi"), Some(vec![0]));
+        assert_eq!(highlights("ﬁle", "file"), Some(vec![0, 1, 2]));
+        assert_eq!(highlights("Straße", "strasse"), Some(vec![0, 1, 2, 3, 4, 5]));
+        assert_eq!(highlights("Strasse", "straße"), Some(vec![0, 1, 2, 3, 4, 5, 6]));
+    }
+
+    #[test]
+    fn initials() {
+        let matcher = Matcher::new(MatchMode::Fuzzy);
+        assert_eq!(matcher.match_initials("LibreOffice", "lo"), Some(vec![0, 5]));
+        assert_eq!(matcher.match_initials("Visual Studio Code", "vsc"), Some(vec![0, 7, 14]));
+        assert_eq!(
+            matcher.match_initials("GNU Image Manipulation Program", "g imp"),
+            Some(vec![0, 4, 10, 23])
+        );
+        assert_eq!(matcher.match_initials("Visual Studio Code", "vsx"), None);
+        assert_eq!(matcher.match_initials("Visual Studio Code", "sv"), None);
+    }
+
+    #[test]
+    fn initials_need_two_letters() {
+        let matcher = Matcher::new(MatchMode::Fuzzy);
+        assert_eq!(matcher.match_initials("Visual Studio Code", "v"), None);
+        assert_eq!(matcher.match_initials("Visual Studio Code", "v "), None);
+    }
+
+    #[test]
+    fn initials_only_fuzzy() {
+        let matcher = Matcher::new(MatchMode::Substring);
+        assert_eq!(matcher.match_initials("LibreOffice", "lo"), None);
+        matcher.set_mode(MatchMode::ClangdFuzzy);
+        assert_eq!(matcher.match_initials("LibreOffice", "lo"), Some(vec![0, 5]));
+    }
+
+    #[test]
+    fn edit_distances() {
+        let distance = |a: &str, b: &str| {
+            edit_distance(&a.chars().collect::<Vec<_>>(), &b.chars().collect::<Vec<_>>())
+        };
+        assert_eq!(distance("firefox", "firefox"), 0);
+        assert_eq!(distance("firfox", "firefox"), 1);
+        // a transposition is a single typo
+        assert_eq!(distance("thunderbrid", "thunderbird"), 1);
+        assert_eq!(distance("ab", "ba"), 1);
+        assert_eq!(distance("ca", "abc"), 3);
+        assert_eq!(distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn typos() {
+        assert_eq!(
+            typo_match("Firefox Web Browser", "firfox", 2),
+            Some((2, vec![0, 1, 2, 3, 4, 5, 6]))
+        );
+        assert_eq!(
+            typo_match("Thunderbird Mail", "thunderbrid", 2),
+            Some((2, (0..11).collect()))
+        );
+        assert_eq!(
+            typo_match("Firefox Web Browser", "firfox brwoser", 2),
+            Some((4, vec![0, 1, 2, 3, 4, 5, 6, 12, 13, 14, 15, 16, 17, 18]))
+        );
+        assert_eq!(typo_match("Firefox Web Browser", "firfox chrome", 2), None);
+    }
+
+    #[test]
+    fn typo_limits() {
+        // one typo per four characters
+        assert_eq!(typo_match("Vim", "vin", 2), None);
+        assert_eq!(typo_match("Gimp", "gipm", 2).map(|(score, _)| score), Some(2));
+        assert_eq!(typo_match("Firefox", "frfx", 2), None);
+        // at most max_typos per word
+        assert!(typo_match("Thunderbird", "thnuderbrid", 2).is_some());
+        assert_eq!(typo_match("Thunderbird", "thnuderbrid", 1), None);
+        assert_eq!(typo_match("Firefox", "firfox", 0), None);
+    }
+
+    #[test]
+    fn typo_word_beginning() {
+        assert_eq!(typo_match("Thunderbird", "thundr", 2), Some((2, vec![0, 1, 2, 3, 4, 5])));
+        // the highlights refer to the chars of choice, not the folded ones
+        assert_eq!(
+            typo_match("Straßenkarte", "strsase", 2),
+            Some((2, vec![0, 1, 2, 3, 4, 5]))
+        );
+    }
+
+    #[test]
+    fn cjk() {
+        assert_eq!(highlights("日本語 テキスト", "本"), Some(vec![1]));
+        assert_eq!(highlights("日本語 テキスト", "テキ"), Some(vec![4, 5]));
+        assert_eq!(highlights("日本語 テキスト", "中"), None);
+    }
+}
@@ -15,14 +15,17 @@ You should have received a copy of the GNU General Public License
 along with sirula.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+use crate::config::{AppOverride, CustomEntry};
 use crate::consts::*;
-use freedesktop_entry_parser::parse_entry;
-use gio::{prelude::AppInfoExt, AppInfo};
-use glib::{shell_parse_argv, GString, ObjectExt};
+use crate::exec::{expand_exec, unescape_string};
+use freedesktop_entry_parser::{parse_entry, Entry};
+use gio::{prelude::AppInfoExt, AppInfo, DesktopAppInfo};
+use glib::{shell_parse_argv, Cast, GString, ObjectExt};
 use gtk::{prelude::CssProviderExt, CssProvider};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::process::{id, Command};
-use shlex::Shlex;
+use shlex::{try_join, Shlex};
 
 pub fn get_xdg_dirs() -> xdg::BaseDirectories {
     xdg::BaseDirectories::with_prefix(APP_NAME).unwrap()
@@ -32,15 +35,19 @@ pub fn get_config_file(file: &str) -> Option<PathBuf> {
     get_xdg_dirs().find_config_file(file)
 }
 
-pub fn get_history_file(place: bool) -> Option<PathBuf> {
+pub fn get_cache_file(file: &str, place: bool) -> Option<PathBuf> {
     let xdg = get_xdg_dirs();
     if place {
-        xdg.place_cache_file(HISTORY_FILE).ok()
+        xdg.place_cache_file(file).ok()
     } else {
-        xdg.find_cache_file(HISTORY_FILE)
+        xdg.find_cache_file(file)
     }
 }
 
+pub fn get_history_file(place: bool) -> Option<PathBuf> {
+    get_cache_file(HISTORY_FILE, place)
+}
+
 pub fn load_css() {
     if let Some(file) = get_config_file(STYLE_FILE) {
         let provider = CssProvider::new();
@@ -70,31 +77,121 @@ pub fn launch_cmd(cmd_line: &str) {
     child.spawn().expect("Error spawning command");
 }
 
-pub fn launch_app(info: &AppInfo, term_command: Option<&str>, launch_cgroups: bool) {
-    let command_string = info
-        .commandline()
-        .unwrap_or_else(|| info.executable())
-        .to_str()
-        .unwrap()
-        .to_string()
-        .replace("%U", "")
-        .replace("%F", "")
-        .replace("%u", "")
-        .replace("%f", "");
-    let mut command: Vec<String> = Shlex::new(&command_string).collect();
-
-    if info
-        .try_property::<GString>("filename")
+pub fn get_desktop_entry(info: &AppInfo) -> Option<Entry> {
+    info.try_property::<GString>("filename")
         .ok()
         .and_then(|s| parse_entry(&s).ok())
-        .and_then(|e| {
-            e.section("Desktop Entry")
-                .attr("Terminal")
-                .map(|t| t == "1" || t == "true")
-        })
-        .unwrap_or_default()
-    {
+}
+
+// expand the field codes of an Exec value of the given app
+pub fn app_command(info: &AppInfo, exec: &str) -> Result<Vec<String>, String> {
+    let desktop_info = info.downcast_ref::<DesktopAppInfo>();
+    let icon = desktop_info.and_then(|d| d.string("Icon"));
+    let file = desktop_info.and_then(|d| d.filename());
+    expand_exec(
+        exec,
+        icon.as_deref(),
+        &info.name(),
+        file.as_deref().and_then(|f| f.to_str()),
+    )
+}
+
+pub fn launch_app(
+    info: &AppInfo,
+    action: Option<&str>,
+    overrides: Option<&AppOverride>,
+    term_command: Option<&str>,
+    in_terminal: bool,
+    launch_cgroups: bool,
+) {
+    let entry = get_desktop_entry(info);
+
+    let exec = match action {
+        Some(action) => match entry
+            .as_ref()
+            .and_then(|e| e.section(format!("Desktop Action {}", action)).attr("Exec"))
+        {
+            Some(exec) => unescape_string(exec),
+            _ => {
+                eprintln!("Desktop action {} has no Exec key", action);
+                return;
+            }
+        },
+        None => info
+            .commandline()
+            .unwrap_or_else(|| info.executable())
+            .to_str()
+            .unwrap()
+            .to_string(),
+    };
+    let mut command = match app_command(info, &exec) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("Cannot parse command line \"{}\": {}", exec, err);
+            return;
+        }
+    };
+
+    let terminal = in_terminal
+        || overrides
+            .and_then(|o| o.terminal)
+            .or_else(|| {
+                entry.as_ref().and_then(|e| {
+                    e.section("Desktop Entry")
+                        .attr("Terminal")
+                        .map(|t| t == "1" || t == "true")
+                })
+            })
+            .unwrap_or_default();
+    let launch_cgroups = overrides.and_then(|o| o.cgroups).unwrap_or(launch_cgroups);
+    let env = overrides.map(|o| o.env.clone()).unwrap_or_default();
+    if let Some(overrides) = overrides {
+        command.extend(overrides.args.iter().cloned());
+    }
+    let mut name = info.id().unwrap().to_string();
+    name.truncate(name.len() - 8); // remove .desktop extension
+    let dir = entry
+        .as_ref()
+        .and_then(|e| e.section("Desktop Entry").attr("Path"))
+        .filter(|p| !p.is_empty())
+        .map(unescape_string);
+    spawn(command, &name, terminal, term_command, launch_cgroups, dir, env);
+}
+
+pub fn launch_custom(
+    custom: &CustomEntry,
+    term_command: Option<&str>,
+    in_terminal: bool,
+    launch_cgroups: bool,
+) {
+    let command = match shell_parse_argv(&custom.command) {
+        Ok(command) if !command.is_empty() => command
+            .into_iter()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect(),
+        _ => {
+            eprintln!("Cannot parse command line \"{}\"", custom.command);
+            return;
+        }
+    };
+    let terminal = in_terminal || custom.terminal;
+    spawn(command, &custom.name, terminal, term_command, launch_cgroups, None, HashMap::new());
+}
+
+// run the command, in a terminal if requested and in its own scope if cgroups are enabled
+fn spawn(
+    mut command: Vec<String>,
+    name: &str,
+    terminal: bool,
+    term_command: Option<&str>,
+    launch_cgroups: bool,
+    dir: Option<String>,
+    env: HashMap<String, String>,
+) {
+    if terminal {
         if let Some(term) = term_command {
+            let command_string = try_join(command.iter().map(String::as_str))
+                .expect("Cannot quote command line");
             let command_string = term.to_string().replace("{}", &command_string);
 		    command = Shlex::new(&command_string).collect();
         } else if let Some(term) = std::env::var_os("TERMINAL") {
@@ -107,8 +204,6 @@ pub fn launch_app(info: &AppInfo, term_command: Option<&str>, launch_cgroups: bo
         };
     }
     if launch_cgroups {
-        let mut name = info.id().unwrap().to_string();
-        name.truncate(name.len() - 8); // remove .desktop extension
         let parsed = Command::new("systemd-escape")
             .arg(name)
             .output()
@@ -124,10 +219,20 @@ pub fn launch_app(info: &AppInfo, term_command: Option<&str>, launch_cgroups: bo
         command = command_new;
     }
 
-    Command::new(&command[0])
-        .args(&command[1..])
-        .spawn()
-        .expect("Error launching app");
+    let mut child = Command::new(&command[0]);
+    child.args(&command[1..]);
+    if let Some(dir) = dir {
+        // a stale Path= should not keep the app from starting
+        if Path::new(&dir).is_dir() {
+            child.current_dir(dir);
+        } else {
+            eprintln!("Working directory {} does not exist", dir);
+        }
+    }
+    child.envs(env);
+    if let Err(err) = child.spawn() {
+        eprintln!("Cannot launch {}: {}", command[0], err);
+    }
 }
 
 #[macro_export]